@@ -1,102 +1,727 @@
-use cpal::{Device, traits::DeviceTrait, traits::HostTrait};
+use crate::error::{Error, Result};
+#[cfg(feature = "capture")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "capture")]
+use cpal::{
+    BufferSize, Device, FromSample, SampleRate, SizedSample, StreamConfig, SupportedBufferSize,
+};
+use hound::WavReader;
 use rustfft::num_complex::Complex32;
-use std::sync::mpsc::{self, Receiver};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
 
+// blocks the live stream and the replay queue before dropping or waiting
+const BLOCK_QUEUE: usize = 100;
+
+/// A multi-channel input the processing thread can pull sample blocks from.
+///
+/// Implemented by the live cpal stream, the WAV file replay and the synthetic
+/// [`SignalGenerator`](crate::generator::SignalGenerator), so the DSP loop does not care
+/// where the samples come from.
+pub trait AudioSource: Send {
+    /// Samples per second of every channel.
+    fn sample_rate(&self) -> u32;
+
+    /// Number of channels returned by [`AudioSource::next_block`].
+    fn channel_count(&self) -> usize;
+
+    /// Starts producing blocks, for the live stream this starts the audio thread.
+    fn start(&mut self) -> Result<()>;
+
+    /// Blocks until the next block is available, one `Vec` per channel.
+    ///
+    /// Returns `None` once the source is exhausted or disconnected.
+    fn next_block(&mut self) -> Option<Vec<Vec<Complex32>>>;
+}
+
+/// Sample formats the live stream can capture, every one is converted to `f32`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    I16,
+    I32,
+    F32,
+}
+
+#[cfg(feature = "capture")]
+impl SampleFormat {
+    fn from_cpal(format: cpal::SampleFormat) -> Option<Self> {
+        match format {
+            cpal::SampleFormat::I16 => Some(SampleFormat::I16),
+            cpal::SampleFormat::I32 => Some(SampleFormat::I32),
+            cpal::SampleFormat::F32 => Some(SampleFormat::F32),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleFormat::I16 => write!(f, "i16"),
+            SampleFormat::I32 => write!(f, "i32"),
+            SampleFormat::F32 => write!(f, "f32"),
+        }
+    }
+}
+
+/// Capture settings requested from the input device, `None` leaves the choice to the device.
+///
+/// Devices rarely support every combination, the closest supported configuration is used:
+/// enough channels first, then the sample rate, then the sample format. The buffer size is
+/// clamped to the range the device reports, and left to the driver when it reports none.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CaptureConfig {
+    pub sample_rate: Option<u32>,
+    /// Frames per callback.
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_format: Option<SampleFormat>,
+}
+
+/// Configuration the device actually runs with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrantedConfig {
+    pub sample_rate: u32,
+    /// `None` when the driver picks the buffer size.
+    pub buffer_size: Option<u32>,
+    pub channels: u16,
+    pub sample_format: SampleFormat,
+}
+
+impl fmt::Display for GrantedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} Hz, {} channels, {}, ",
+            self.sample_rate, self.channels, self.sample_format
+        )?;
+        match self.buffer_size {
+            Some(frames) => write!(f, "{frames} frame buffer"),
+            None => write!(f, "default buffer"),
+        }
+    }
+}
+
+/// Input device of one audio host, as listed by `list_input_devices`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    /// Position in the list, what [`DeviceSelector::Index`] refers to.
+    pub index: usize,
+    pub host: String,
+    pub name: String,
+    pub configs: Vec<ConfigRange>,
+}
+
+/// One supported input configuration of a device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// `None` for formats the stream can't capture.
+    pub sample_format: Option<SampleFormat>,
+}
+
+impl fmt::Display for ConfigRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} channels, {}-{} Hz, ",
+            self.channels, self.min_sample_rate, self.max_sample_rate
+        )?;
+        match self.sample_format {
+            Some(format) => write!(f, "{format}"),
+            None => write!(f, "unsupported format"),
+        }
+    }
+}
+
+/// Every input device of every available host, in a stable order.
+#[cfg(feature = "capture")]
+pub fn list_input_devices() -> Vec<DeviceInfo> {
+    input_devices()
+        .into_iter()
+        .enumerate()
+        .map(|(index, (host, device))| DeviceInfo {
+            index,
+            host,
+            name: device.name().unwrap_or_else(|_| String::from("unnamed")),
+            configs: device
+                .supported_input_configs()
+                .map(|configs| {
+                    configs
+                        .map(|x| ConfigRange {
+                            channels: x.channels(),
+                            min_sample_rate: x.min_sample_rate().0,
+                            max_sample_rate: x.max_sample_rate().0,
+                            sample_format: SampleFormat::from_cpal(x.sample_format()),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(feature = "capture")]
+fn input_devices() -> Vec<(String, Device)> {
+    cpal::available_hosts()
+        .into_iter()
+        .filter_map(|id| cpal::host_from_id(id).ok())
+        .flat_map(|host| {
+            let name = host.id().name().to_string();
+            host.input_devices()
+                .map(|devices| devices.map(|x| (name.clone(), x)).collect::<Vec<_>>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Which input device to capture from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The default input device of the default host.
+    Default,
+    /// Position in `list_input_devices`.
+    Index(usize),
+    /// Exact device name, falling back to a unique case insensitive substring match.
+    Name(String),
+    /// Unique case insensitive part of the device name, written `*part*`.
+    Substring(String),
+}
+
+impl DeviceSelector {
+    /// `default`, an index, `*part*` of a name or a name, as given on the command line.
+    pub fn parse(selector: &str) -> Self {
+        if selector.eq_ignore_ascii_case("default") {
+            DeviceSelector::Default
+        } else if let Ok(index) = selector.parse::<usize>() {
+            DeviceSelector::Index(index)
+        } else if let Some(part) = selector
+            .strip_prefix('*')
+            .and_then(|x| x.strip_suffix('*'))
+            .filter(|x| !x.is_empty())
+        {
+            DeviceSelector::Substring(part.to_string())
+        } else {
+            DeviceSelector::Name(selector.to_string())
+        }
+    }
+
+    #[cfg(feature = "capture")]
+    fn select(&self) -> Result<Device> {
+        let mut devices = input_devices();
+        let names: Vec<String> = devices
+            .iter()
+            .map(|(_, x)| x.name().unwrap_or_default())
+            .collect();
+        let available = names
+            .iter()
+            .enumerate()
+            .map(|(i, x)| format!("  {i}: {x}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let substring = |part: &str| {
+            let part = part.to_lowercase();
+            let matches: Vec<usize> = (0..names.len())
+                .filter(|i| names[*i].to_lowercase().contains(&part))
+                .collect();
+            match matches.as_slice() {
+                [index] => Ok(*index),
+                [] => Err(Error::Device(format!(
+                    "No input device matches {self}, available:\n{available}"
+                ))),
+                _ => Err(Error::Device(format!(
+                    "{self} matches {} input devices, available:\n{available}",
+                    matches.len()
+                ))),
+            }
+        };
+
+        let index = match self {
+            DeviceSelector::Default => {
+                return cpal::default_host()
+                    .default_input_device()
+                    .ok_or_else(|| Error::Device(String::from("No default input device")));
+            }
+            DeviceSelector::Index(index) if *index < devices.len() => Ok(*index),
+            DeviceSelector::Index(_) => Err(Error::Device(format!(
+                "No input device {self}, available:\n{available}"
+            ))),
+            DeviceSelector::Name(name) => match names.iter().position(|x| x == name) {
+                Some(index) => Ok(index),
+                None => substring(name),
+            },
+            DeviceSelector::Substring(part) => substring(part),
+        }?;
+
+        Ok(devices.swap_remove(index).1)
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Default => write!(f, "default"),
+            DeviceSelector::Index(index) => write!(f, "#{index}"),
+            DeviceSelector::Name(name) => write!(f, "{name:?}"),
+            DeviceSelector::Substring(part) => write!(f, "*{part}*"),
+        }
+    }
+}
+
+/// Live capture from an input device.
+#[cfg(feature = "capture")]
 pub struct StreamEncapsulate {
     pub stream: cpal::Stream,
-    pub left_rx: Receiver<Vec<Complex32>>,
-    pub right_rx: Receiver<Vec<Complex32>>,
+    /// Every callback as one block per device channel, in the device's channel order.
+    pub block_rx: Receiver<Vec<Vec<Complex32>>>,
+    pub channels: usize,
     pub samples_per_sec: u32,
+    pub device_name: String,
+    pub granted: GrantedConfig,
 }
 
+#[cfg(feature = "capture")]
 impl StreamEncapsulate {
-    pub fn new(device_name: &str) -> Self {
-        let host = cpal::default_host();
+    /// Opens the input device `selector` picks with the supported configuration closest to
+    /// `capture`, see [`CaptureConfig`]. What the device granted is printed and kept in
+    /// `granted`.
+    pub fn new(selector: &DeviceSelector, capture: &CaptureConfig) -> Result<Self> {
+        let input = selector.select()?;
+        let device_name = input.name().unwrap_or_else(|_| String::from("unnamed"));
+        println!("Input Device: {device_name}");
 
-        let mut input: Vec<Device> = host
-            .input_devices()
-            .unwrap()
-            .filter(|x| {
-                let name = x.name().unwrap();
-                dbg!(&name);
-                name == device_name
-            })
-            .collect();
+        let (config, sample_format, granted) = choose_config(&input, capture)?;
+        println!("Capture: {granted}");
+
+        let channels = config.channels as usize;
+        if channels < 2 {
+            return Err(Error::Device(format!(
+                "Input device needs at least 2 channels, found {channels}"
+            )));
+        }
+
+        let (block_tx, block_rx) = mpsc::sync_channel(BLOCK_QUEUE);
+
+        let stream = match sample_format {
+            SampleFormat::I16 => build_input_stream::<i16>(&input, &config, block_tx),
+            SampleFormat::I32 => build_input_stream::<i32>(&input, &config, block_tx),
+            SampleFormat::F32 => build_input_stream::<f32>(&input, &config, block_tx),
+        }?;
+
+        Ok(StreamEncapsulate {
+            stream,
+            block_rx,
+            channels,
+            samples_per_sec: granted.sample_rate,
+            device_name,
+            granted,
+        })
+    }
+}
+
+#[cfg(feature = "capture")]
+impl AudioSource for StreamEncapsulate {
+    fn sample_rate(&self) -> u32 {
+        self.samples_per_sec
+    }
+
+    fn channel_count(&self) -> usize {
+        self.channels
+    }
+
+    fn start(&mut self) -> Result<()> {
+        // Runs the thread
+        self.stream
+            .play()
+            .map_err(|e| Error::Stream(format!("Couldn't start the stream: {e}")))
+    }
+
+    fn next_block(&mut self) -> Option<Vec<Vec<Complex32>>> {
+        self.block_rx.recv().ok()
+    }
+}
+
+pub struct FileStreamEncapsulate {
+    /// Every block read as one block per channel of the file.
+    pub block_rx: Receiver<Vec<Vec<Complex32>>>,
+    pub channels: usize,
+    pub samples_per_sec: u32,
+    pending: Option<FileReplay>,
+}
+
+struct FileReplay {
+    reader: WavReader<BufReader<File>>,
+    block_tx: SyncSender<Vec<Vec<Complex32>>>,
+    block_size: usize,
+    realtime: bool,
+}
+
+impl FileStreamEncapsulate {
+    /// Replays a WAV file through the same kind of block receiver as the live stream.
+    ///
+    /// Every channel of the file is replayed, the first two are left and right. With `realtime`
+    /// the blocks are paced at the file's sample rate and dropped like the live stream
+    /// when the receiver falls behind, otherwise every block is delivered as fast as
+    /// the receiver can take it. Nothing is read until [`AudioSource::start`].
+    pub fn new(path: &str, block_size: usize, realtime: bool) -> Result<Self> {
+        if block_size == 0 {
+            return Err(Error::Config(String::from("Block size must be non zero")));
+        }
+
+        let reader = WavReader::open(path)
+            .map_err(|e| Error::Stream(format!("Couldn't open {path}: {e}")))?;
+        let spec = reader.spec();
 
-        let input = input.pop().expect("No Input Vector");
-        println!("Input Device: {}", input.name().expect("No Name For Input"));
-
-        // let input = host.default_input_device().unwrap();
-
-        // for config in input.supported_input_configs().unwrap() {
-        //     //config.buffer_size()
-        //     dbg!(config);
-        // }
-
-        let config = input
-            .default_input_config()
-            .expect("No Default Input Configuration")
-            .config();
-
-        // config.buffer_size = BufferSize::Fixed(44100);
-        // config.buffer_size = BufferSize::Fixed(10000);
-        // dbg!(host.input_devices());
-
-        dbg!(&config);
-
-        let samples_per_sec = config.sample_rate.0;
-
-        dbg!(samples_per_sec);
-
-        let (tx_right, rx_right) = mpsc::sync_channel::<Vec<Complex32>>(100);
-        let (tx_left, rx_left) = mpsc::sync_channel::<Vec<Complex32>>(100);
-
-        let stream = input
-            .build_input_stream(
-                &config,
-                move |x: &[f32], _a: &cpal::InputCallbackInfo| {
-                    // runs in another thread
-                    let even_left: Vec<Complex32> =
-                        x.iter().step_by(2).map(|x| Complex32::from(x)).collect();
-                    let odd_right: Vec<Complex32> = x
-                        .iter()
-                        .skip(1)
-                        .step_by(2)
-                        .map(|x| Complex32::from(x))
-                        .collect();
-
-                    // println!(
-                    //     "ADC: {}\nCal: {}",
-                    //     _a.timestamp()
-                    //         .capture
-                    //         .duration_since(&StreamInstant::new(0, 0))
-                    //         .unwrap(),
-                    //     _a.timestamp()
-                    //         .callback
-                    //         .duration_since(&StreamInstant::new(0, 0))
-                    //         .unwrap()
-                    // );
-
-                    // dbg!(&_a);
-
-                    // drop data if the FFT is not fast enough in reciever
-                    if let Ok(_) = tx_left.try_send(even_left) {}
-                    if let Ok(_) = tx_right.try_send(odd_right) {}
-                },
-                |err| {
-                    // runs in another thread
-                    // Callback Here
-                    eprint!("[ERROR]: {err}");
-                },
-                None,
+        if spec.channels < 2 {
+            return Err(Error::Stream(format!(
+                "{path} needs at least 2 channels, found {}",
+                spec.channels
+            )));
+        }
+
+        let (block_tx, block_rx) = mpsc::sync_channel(BLOCK_QUEUE);
+
+        Ok(FileStreamEncapsulate {
+            block_rx,
+            channels: spec.channels as usize,
+            samples_per_sec: spec.sample_rate,
+            pending: Some(FileReplay {
+                reader,
+                block_tx,
+                block_size,
+                realtime,
+            }),
+        })
+    }
+}
+
+impl FileReplay {
+    fn run(self) {
+        let FileReplay {
+            reader,
+            block_tx,
+            block_size,
+            realtime,
+        } = self;
+
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+        let samples_per_sec = spec.sample_rate;
+
+        // a corrupt file ends the replay like reaching its end
+        let report = |e: hound::Error| eprintln!("Couldn't read WAV sample: {e}");
+
+        let samples: Box<dyn Iterator<Item = f32>> = match spec.sample_format {
+            hound::SampleFormat::Float => Box::new(
+                reader
+                    .into_samples::<f32>()
+                    .map_while(move |x| x.map_err(report).ok()),
+            ),
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                Box::new(
+                    reader
+                        .into_samples::<i32>()
+                        .map_while(move |x| x.map_err(report).ok())
+                        .map(move |x| x as f32 * scale),
+                )
+            }
+        };
+
+        let start = Instant::now();
+        let mut sent_frames: u64 = 0;
+
+        let mut blocks: Vec<Vec<Complex32>> = vec![Vec::with_capacity(block_size); channels];
+
+        for (i, x) in samples.enumerate() {
+            blocks[i % channels].push(Complex32::from(x));
+
+            if i % channels != channels - 1 || blocks[channels - 1].len() < block_size {
+                continue;
+            }
+
+            sent_frames += block_size as u64;
+
+            let ready: Vec<Vec<Complex32>> = blocks
+                .iter_mut()
+                .map(|block| std::mem::replace(block, Vec::with_capacity(block_size)))
+                .collect();
+
+            if realtime {
+                let due = Duration::from_secs_f64(sent_frames as f64 / samples_per_sec as f64);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+
+                // drop data if the FFT is not fast enough in reciever, like the live stream
+                let _ = block_tx.try_send(ready);
+            } else if block_tx.send(ready).is_err() {
+                return;
+            }
+        }
+
+        println!("Finished replaying {} samples", sent_frames);
+    }
+}
+
+impl AudioSource for FileStreamEncapsulate {
+    fn sample_rate(&self) -> u32 {
+        self.samples_per_sec
+    }
+
+    fn channel_count(&self) -> usize {
+        self.channels
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if let Some(replay) = self.pending.take() {
+            thread::spawn(move || replay.run()); // Spawned New Thread Here
+        }
+        Ok(())
+    }
+
+    fn next_block(&mut self) -> Option<Vec<Vec<Complex32>>> {
+        self.block_rx.recv().ok()
+    }
+}
+
+/// Writes the blocks of a started `source` to a 32 bit float WAV file, which
+/// [`FileStreamEncapsulate`] replays, until `duration` is recorded or the source ends.
+///
+/// The header is updated after every block, so a recording cut short by killing the process
+/// still opens. Returns the number of samples written per channel.
+pub fn record(source: &mut dyn AudioSource, path: &str, duration: Option<Duration>) -> Result<u64> {
+    let spec = hound::WavSpec {
+        channels: source.channel_count() as u16,
+        sample_rate: source.sample_rate(),
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+
+    let error = |e: hound::Error| Error::Stream(format!("Couldn't write {path}: {e}"));
+    let mut writer = hound::WavWriter::create(path, spec).map_err(error)?;
+
+    let limit = duration.map(|x| (x.as_secs_f64() * spec.sample_rate as f64) as u64);
+    let mut written: u64 = 0;
+
+    while limit.is_none_or(|limit| written < limit) {
+        let Some(channels) = source.next_block() else {
+            break;
+        };
+
+        let len = channels.iter().map(|x| x.len()).min().unwrap_or(0);
+        let len = limit.map_or(len, |limit| len.min((limit - written) as usize));
+
+        for i in 0..len {
+            for channel in &channels {
+                writer.write_sample(channel[i].re).map_err(error)?;
+            }
+        }
+
+        written += len as u64;
+        writer.flush().map_err(error)?;
+    }
+
+    writer.finalize().map_err(error)?;
+    Ok(written)
+}
+
+/// Picks the supported configuration closest to `capture`.
+#[cfg(feature = "capture")]
+fn choose_config(
+    input: &Device,
+    capture: &CaptureConfig,
+) -> Result<(StreamConfig, SampleFormat, GrantedConfig)> {
+    let default = input
+        .default_input_config()
+        .map_err(|e| Error::Device(format!("No default input configuration: {e}")))?;
+
+    let wanted_rate = capture.sample_rate.unwrap_or(default.sample_rate().0);
+    let wanted_channels = capture.channels.unwrap_or(default.channels()).max(2);
+
+    let (range, format) = input
+        .supported_input_configs()
+        .map_err(|e| Error::Device(format!("Couldn't query the input configurations: {e}")))?
+        .filter_map(|range| Some((range, SampleFormat::from_cpal(range.sample_format())?)))
+        .min_by_key(|(range, format)| {
+            let rate = wanted_rate.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+            (
+                // too few channels is worse than too many
+                range.channels() < wanted_channels,
+                range.channels().abs_diff(wanted_channels),
+                rate.abs_diff(wanted_rate),
+                capture.sample_format.is_some_and(|x| x != *format),
             )
-            .expect("Couldn't Create the Stream");
+        })
+        .ok_or_else(|| {
+            Error::Device(String::from(
+                "Input device has no i16, i32 or f32 configuration",
+            ))
+        })?;
+
+    let rate = wanted_rate.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+    let supported = range.with_sample_rate(SampleRate(rate));
+
+    let buffer_size = match (capture.buffer_size, supported.buffer_size()) {
+        (Some(frames), SupportedBufferSize::Range { min, max }) => Some(frames.clamp(*min, *max)),
+        _ => None,
+    };
+
+    let mut config = supported.config();
+    if let Some(frames) = buffer_size {
+        config.buffer_size = BufferSize::Fixed(frames);
+    }
+
+    let granted = GrantedConfig {
+        sample_rate: config.sample_rate.0,
+        buffer_size,
+        channels: config.channels,
+        sample_format: format,
+    };
+
+    Ok((config, format, granted))
+}
+
+#[cfg(feature = "capture")]
+fn build_input_stream<T>(
+    input: &Device,
+    config: &StreamConfig,
+    block_tx: SyncSender<Vec<Vec<Complex32>>>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+
+    input
+        .build_input_stream(
+            config,
+            move |x: &[T], _a: &cpal::InputCallbackInfo| {
+                // runs in another thread
+                let deinterleaved: Vec<Vec<Complex32>> = (0..channels)
+                    .map(|c| {
+                        x.iter()
+                            .skip(c)
+                            .step_by(channels)
+                            .map(|x| Complex32::from(x.to_sample::<f32>()))
+                            .collect()
+                    })
+                    .collect();
+
+                // drop data if the FFT is not fast enough in reciever, all channels of a
+                // callback together so they stay aligned
+                let _ = block_tx.try_send(deinterleaved);
+            },
+            |err| {
+                // runs in another thread
+                // Callback Here
+                eprint!("[ERROR]: {err}");
+            },
+            None,
+        )
+        .map_err(|e| Error::Stream(format!("Couldn't create the stream: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcc::GccEstimator;
+    use crate::generator::{SignalGenerator, SourceKind};
+
+    fn generator() -> SignalGenerator {
+        SignalGenerator::new(48000, 1000, SourceKind::WhiteNoise, 250e-6)
+            .unwrap()
+            .with_noise(30.0)
+    }
+
+    #[test]
+    fn replays_a_recording_sample_for_sample() {
+        let path = std::env::temp_dir().join(format!("vdf-replay-{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let written = record(&mut generator(), path, Some(Duration::from_millis(250))).unwrap();
+        assert_eq!(written, 12000);
+
+        let mut replay = FileStreamEncapsulate::new(path, 1024, false).unwrap();
+        assert_eq!(replay.sample_rate(), 48000);
+        assert_eq!(replay.channel_count(), 2);
+        replay.start().unwrap();
+
+        let mut replayed: Vec<Vec<Complex32>> = vec![Vec::new(); 2];
+        while let Some(block) = replay.next_block() {
+            assert!(block.iter().all(|x| x.len() == 1024));
+            for (channel, samples) in replayed.iter_mut().zip(block) {
+                channel.extend(samples);
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+
+        // only whole blocks are replayed
+        assert_eq!(replayed[0].len(), 11 * 1024);
+
+        let mut original: Vec<Vec<Complex32>> = vec![Vec::new(); 2];
+        let mut source = generator();
+        while original[0].len() < replayed[0].len() {
+            for (channel, samples) in original.iter_mut().zip(source.next_block().unwrap()) {
+                channel.extend(samples);
+            }
+        }
+        for (original, replayed) in original.iter().zip(&replayed) {
+            assert_eq!(&original[..replayed.len()], &replayed[..]);
+        }
+
+        let mut estimator = GccEstimator::new(48000);
+        let estimate = estimator
+            .estimate(&replayed[0][..4096], &replayed[1][..4096])
+            .unwrap();
+        assert!((estimate.delay - 250e-6).abs() < 5e-6, "{}", estimate.delay);
+    }
+
+    #[test]
+    fn replay_rejects_missing_files_and_empty_blocks() {
+        assert!(matches!(
+            FileStreamEncapsulate::new("/nonexistent/recording.wav", 1024, false),
+            Err(Error::Stream(_))
+        ));
+
+        let path = std::env::temp_dir().join(format!("vdf-empty-{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+        record(&mut generator(), path, Some(Duration::from_millis(10))).unwrap();
+        let result = FileStreamEncapsulate::new(path, 0, false);
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parses_device_selectors() {
+        for (selector, expected) in [
+            ("default", DeviceSelector::Default),
+            ("Default", DeviceSelector::Default),
+            ("3", DeviceSelector::Index(3)),
+            ("USB Audio", DeviceSelector::Name(String::from("USB Audio"))),
+            ("*usb*", DeviceSelector::Substring(String::from("usb"))),
+            ("*usb", DeviceSelector::Name(String::from("*usb"))),
+            ("**", DeviceSelector::Name(String::from("**"))),
+        ] {
+            assert_eq!(DeviceSelector::parse(selector), expected, "{selector}");
+        }
+    }
 
-        StreamEncapsulate {
-            stream: stream,
-            left_rx: rx_left,
-            right_rx: rx_right,
-            samples_per_sec,
+    #[test]
+    fn selectors_display_as_they_parse() {
+        for selector in ["default", "*usb*", "\"USB Audio\""] {
+            let parsed = DeviceSelector::parse(selector.trim_matches('"'));
+            assert_eq!(parsed.to_string(), selector);
         }
     }
 }
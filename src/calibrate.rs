@@ -0,0 +1,139 @@
+use rustfft::num_complex::Complex32;
+use voice_direction_finder::audio::AudioSource;
+use voice_direction_finder::cfar_detections;
+use voice_direction_finder::config::Config;
+use voice_direction_finder::error::{Error, Result};
+use voice_direction_finder::frame::Framer;
+use voice_direction_finder::gcc::{self, GccEstimator};
+use voice_direction_finder::signal::SignalProcessor;
+use voice_direction_finder::vad::VoiceActivityDetector;
+
+/// Measures the delay of the reference pair for `seconds` of speech from a source at `angle`
+/// radians from its broadside and compares it with the configured geometry.
+///
+/// Off broadside the median delay gives the spacing of the pair, at broadside any delay left
+/// is an offset between the two channels.
+pub fn run(config: &Config, source: &mut dyn AudioSource, angle: f32, seconds: f64) -> Result<()> {
+    let geometry = config.array.geometry()?;
+    let (left, right) = geometry.reference_pair();
+    if right >= source.channel_count() {
+        return Err(Error::Config(format!(
+            "Calibration needs channel {right} but the input only has {}",
+            source.channel_count()
+        )));
+    }
+
+    let dsp = &config.dsp;
+    let spacing = geometry.distance(left, right).unwrap_or_default();
+
+    let mut signal_processor = SignalProcessor::new(source.sample_rate());
+    let mut framers = [
+        Framer::new(dsp.frame_len, dsp.frame_hop, dsp.window)?,
+        Framer::new(dsp.frame_len, dsp.frame_hop, dsp.window)?,
+    ];
+
+    // the spacing is what is being checked, so the lag window is twice as wide as usual
+    let mut estimator = GccEstimator::new(source.sample_rate());
+    estimator.set_weighting(dsp.weighting);
+    estimator.set_band(dsp.voice_band);
+    estimator.set_min_bins(dsp.cfar.min_bins);
+    estimator.set_max_lag(Some(gcc::max_lag(spacing, 1.0)));
+
+    let mut vad = VoiceActivityDetector::new(source.sample_rate(), dsp.vad);
+    if let Some(band) = dsp.voice_band {
+        vad.set_band(band);
+    }
+
+    let total_frames = (seconds * source.sample_rate() as f64 / dsp.frame_hop as f64) as usize;
+    let mut frames_seen = 0;
+    let mut delays: Vec<f32> = Vec::new();
+
+    println!(
+        "Calibrating channels {left} and {right} for {seconds} s, keep the source at {:.0}°",
+        angle.to_degrees()
+    );
+
+    while frames_seen < total_frames {
+        let Some(channels) = source.next_block() else {
+            break;
+        };
+
+        let left_frames = framers[0].push(&channels[left]);
+        let right_frames = framers[1].push(&channels[right]);
+
+        for (mut left_frame, mut right_frame) in left_frames.into_iter().zip(right_frames) {
+            frames_seen += 1;
+
+            let left_fft = signal_processor.fft(&mut left_frame);
+            let right_fft = signal_processor.fft(&mut right_frame);
+
+            let detected = detections(&mut signal_processor, &left_fft, config)?;
+            if !vad.is_speech(&left_fft, Some(&detected)) {
+                continue;
+            }
+
+            let gating = dsp.cfar.gating.then_some(detected.as_slice());
+            if let Ok(estimate) = estimator.estimate_from_spectra(&left_fft, &right_fft, gating) {
+                delays.push(estimate.delay);
+            }
+        }
+    }
+
+    if delays.is_empty() {
+        return Err(Error::Dsp("no speech was detected while calibrating"));
+    }
+
+    delays.sort_by(|a, b| a.total_cmp(b));
+    let delay = delays[delays.len() / 2];
+    let expected = geometry
+        .angle_to_delay(left, right, angle)
+        .unwrap_or_default();
+
+    println!(
+        "{} of {frames_seen} frames had speech, median delay {:.1} µs, expected {:.1} µs",
+        delays.len(),
+        delay * 1e6,
+        expected * 1e6
+    );
+
+    // below 30° the spacing is too sensitive to the delay resolution
+    if angle.sin().abs() < 0.5 {
+        println!(
+            "Channel offset: {:.1} µs, move the source off broadside to measure the spacing",
+            (delay - expected) * 1e6
+        );
+        return Ok(());
+    }
+
+    let measured = delay as f64 * voice_direction_finder::SPEED_SOUND / angle.sin() as f64;
+    if measured <= 0.0 {
+        println!("The delay has the wrong sign, are channels {left} and {right} swapped?");
+        return Ok(());
+    }
+
+    println!("Configured spacing {spacing:.4} m, measured {measured:.4} m");
+    if config.array.microphones.is_empty() && config.array.geometry_file.is_none() {
+        println!("[array]\nmic_distance = {measured:.4}");
+    }
+
+    Ok(())
+}
+
+/// CFAR detections of one spectrum, as the processing loop computes them.
+fn detections(
+    signal_processor: &mut SignalProcessor,
+    spectrum: &[Complex32],
+    config: &Config,
+) -> Result<Vec<bool>> {
+    let cfar = &config.dsp.cfar;
+    let plot = signal_processor.complex_fft_to_db_magnitude(spectrum);
+    let magnitude: Vec<f32> = plot.iter().map(|(_, y)| *y).collect();
+    let threshold = signal_processor.add_frequency_resolution(SignalProcessor::cfar(
+        &magnitude,
+        cfar.gap,
+        cfar.reference,
+        cfar.bias,
+    ));
+
+    cfar_detections(&plot, &threshold)
+}
@@ -0,0 +1,652 @@
+use crate::audio::{CaptureConfig, SampleFormat};
+use crate::error::{Error, Result};
+use crate::frame::Window;
+use crate::gcc::Weighting;
+use crate::geometry::{ArrayGeometry, Microphone};
+use crate::vad::VadMethod;
+use serde::Deserialize;
+use std::fs;
+use toml::{Table, Value};
+
+/// Settings of one node, read from a TOML file with one table per section.
+///
+/// The `[node]` pose is required, no default fits every node. Every other section and key
+/// is optional and falls back to its default, so a node only has to list what differs.
+/// Unknown keys are rejected to catch typos.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub node: NodeSettings,
+    #[serde(default)]
+    pub array: ArraySettings,
+    #[serde(default)]
+    pub capture: CaptureSettings,
+    #[serde(default)]
+    pub dsp: DspSettings,
+    #[serde(default)]
+    pub tracking: TrackingSettings,
+    #[serde(default)]
+    pub network: NetworkSettings,
+    #[serde(default)]
+    pub ui: UiSettings,
+}
+
+/// Pose of the node, sent along with every estimate.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSettings {
+    pub h: f64,
+    pub k: f64,
+    pub phi: f64,
+}
+
+/// Microphone layout. Inline `microphones` take precedence over a `geometry_file`, without
+/// either the first two channels are a bar `mic_distance` metres wide.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArraySettings {
+    pub mic_distance: f64,
+    /// Geometry file in the format of [`ArrayGeometry::parse`].
+    pub geometry_file: Option<String>,
+    pub microphones: Vec<Microphone>,
+}
+
+impl Default for ArraySettings {
+    fn default() -> Self {
+        ArraySettings {
+            mic_distance: 0.2,
+            geometry_file: None,
+            microphones: Vec::new(),
+        }
+    }
+}
+
+impl ArraySettings {
+    pub fn geometry(&self) -> Result<ArrayGeometry> {
+        if !self.microphones.is_empty() {
+            ArrayGeometry::new(self.microphones.clone())
+        } else if let Some(path) = &self.geometry_file {
+            ArrayGeometry::load(path)
+        } else {
+            ArrayGeometry::two_mic_bar(self.mic_distance)
+        }
+    }
+}
+
+/// Input device and the configuration requested from it, see [`CaptureConfig`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureSettings {
+    /// `"default"`, an index from the device list, a name or `"*part*"` of one, see
+    /// [`DeviceSelector::parse`](crate::audio::DeviceSelector::parse).
+    pub device: String,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_format: Option<SampleFormat>,
+    /// Samples per channel handed out per block when replaying a WAV file.
+    pub replay_block_size: usize,
+    /// Paces the replay like a live device instead of running as fast as possible.
+    pub replay_realtime: bool,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        CaptureSettings {
+            device: String::from("default"),
+            // a higher sample rate directly improves the time resolution of the delay
+            sample_rate: Some(48000),
+            buffer_size: Some(1024),
+            channels: None,
+            sample_format: None,
+            replay_block_size: 1024,
+            replay_realtime: true,
+        }
+    }
+}
+
+impl CaptureSettings {
+    pub fn capture_config(&self) -> CaptureConfig {
+        CaptureConfig {
+            sample_rate: self.sample_rate,
+            buffer_size: self.buffer_size,
+            channels: self.channels,
+            sample_format: self.sample_format,
+        }
+    }
+}
+
+/// Framing, weighting and the estimators run on every frame.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DspSettings {
+    pub frame_len: usize,
+    pub frame_hop: usize,
+    pub window: Window,
+    pub weighting: Weighting,
+    /// Fraction the largest physical lag of a pair is widened by, see
+    /// [`max_lag`](crate::gcc::max_lag).
+    pub lag_margin: f64,
+    /// Frequency range in Hz the GCC, VAD and MUSIC look at, `None` uses every bin.
+    pub voice_band: Option<(f32, f32)>,
+    pub cfar: CfarSettings,
+    pub vad: VadMethod,
+    pub srp_azimuth_steps: usize,
+    /// Only used by arrays that aren't planar.
+    pub srp_elevation_steps: usize,
+    /// Talkers MUSIC looks for, limited to one less than the microphones.
+    pub music_sources: usize,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        DspSettings {
+            frame_len: 1024,
+            frame_hop: 512,
+            window: Window::Hann,
+            weighting: Weighting::Phat,
+            lag_margin: 0.1,
+            voice_band: Some((300.0, 4000.0)),
+            cfar: CfarSettings::default(),
+            vad: VadMethod::EnergyFlatness {
+                energy_margin_db: 6.0,
+                max_flatness: 0.4,
+            },
+            srp_azimuth_steps: 72,
+            srp_elevation_steps: 10,
+            music_sources: 2,
+        }
+    }
+}
+
+/// CFAR threshold of the magnitude spectra, see
+/// [`SignalProcessor::cfar`](crate::signal::SignalProcessor::cfar).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CfarSettings {
+    /// Only bins above the threshold feed the GCC and MUSIC.
+    pub gating: bool,
+    pub gap: usize,
+    pub reference: usize,
+    /// Fewest in-band bins the gating may leave, frames with fewer get no GCC estimate.
+    pub min_bins: usize,
+    /// Offset of the threshold above the mean of the reference cells, in dB.
+    pub bias: f32,
+}
+
+impl Default for CfarSettings {
+    fn default() -> Self {
+        CfarSettings {
+            gating: false,
+            gap: 10,
+            reference: 4,
+            min_bins: 8,
+            bias: 3.5,
+        }
+    }
+}
+
+/// Kalman trackers and the post-filter of the reported angles.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackingSettings {
+    /// Angular acceleration density in rad²/s³.
+    pub process_noise: f32,
+    /// Spread of a fully confident estimate in radians.
+    pub measurement_noise: f32,
+    /// Gate in standard deviations of the innovation.
+    pub gate: f32,
+    /// A talker is confirmed after `m` hits in its first `n` frames, as `[m, n]`.
+    pub birth: (usize, usize),
+    pub max_misses: usize,
+    /// Seconds a talker is kept without a detection, pauses included.
+    pub max_coast: f32,
+    /// How fast the reported angle may change, in Hz.
+    pub post_filter_cutoff: f32,
+}
+
+impl Default for TrackingSettings {
+    fn default() -> Self {
+        TrackingSettings {
+            process_noise: 2.0,
+            measurement_noise: 0.05,
+            gate: 3.0,
+            birth: (3, 5),
+            max_misses: 30,
+            max_coast: 2.0,
+            post_filter_cutoff: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    /// `host:port` of the server the estimates are sent to.
+    pub server: String,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            server: String::from("10.84.222.62:9099"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiSettings {
+    /// Frames of angle history kept for the plots.
+    pub history_len: usize,
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        UiSettings { history_len: 120 }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Self::parse(&contents).map_err(|e| match e {
+            Error::Config(message) => Error::Config(format!("{path}: {message}")),
+            e => e,
+        })
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Config = toml::from_str(contents)
+            .map_err(|e| Error::Config(e.to_string().trim_end().to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads `path`, or starts from an empty file without one, and applies `key=value`
+    /// overrides such as `dsp.weighting="scot"` on top. Values are TOML, a value that isn't
+    /// valid TOML is taken as a string, so `capture.device=USB Audio` works unquoted.
+    pub fn load_with_overrides(path: Option<&str>, overrides: &[String]) -> Result<Self> {
+        if let Some(path) = path
+            && overrides.is_empty()
+        {
+            return Self::load(path);
+        }
+
+        let mut table = match path {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|e| Error::io(path, e))?
+                .parse::<Table>()
+                .map_err(|e| Error::Config(format!("{path}: {}", e.to_string().trim_end())))?,
+            None => Table::new(),
+        };
+
+        for item in overrides {
+            set_override(&mut table, item)?;
+        }
+
+        let config: Config = Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| {
+                Error::Config(format!(
+                    "{} with overrides: {}",
+                    path.unwrap_or("no file"),
+                    e.to_string().trim_end()
+                ))
+            })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values serde can't, naming the offending key.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |key: &str, message: String| Err(Error::Config(format!("{key} {message}")));
+
+        if [self.node.h, self.node.k, self.node.phi]
+            .iter()
+            .any(|x| !x.is_finite())
+        {
+            return invalid("node", String::from("needs finite h, k and phi"));
+        }
+
+        if !(self.array.mic_distance > 0.0 && self.array.mic_distance.is_finite()) {
+            return invalid(
+                "array.mic_distance",
+                format!("must be positive, found {}", self.array.mic_distance),
+            );
+        }
+
+        // reads the geometry file too, so a missing one fails at load rather than at start
+        match self.array.geometry() {
+            Ok(_) => {}
+            Err(Error::Config(message)) => return invalid("array", message),
+            Err(e) => return Err(e),
+        }
+
+        if self.capture.replay_block_size == 0 {
+            return invalid(
+                "capture.replay_block_size",
+                String::from("must be positive"),
+            );
+        }
+
+        let dsp = &self.dsp;
+        if dsp.frame_hop == 0 || dsp.frame_hop > dsp.frame_len {
+            return invalid(
+                "dsp.frame_hop",
+                format!(
+                    "must be between 1 and dsp.frame_len ({}), found {}",
+                    dsp.frame_len, dsp.frame_hop
+                ),
+            );
+        }
+
+        if !(dsp.lag_margin >= 0.0 && dsp.lag_margin.is_finite()) {
+            return invalid(
+                "dsp.lag_margin",
+                format!("must not be negative, found {}", dsp.lag_margin),
+            );
+        }
+
+        if let Some((low, high)) = dsp.voice_band
+            && !(low >= 0.0 && low < high)
+        {
+            return invalid(
+                "dsp.voice_band",
+                format!("needs 0 <= low < high, found [{low}, {high}]"),
+            );
+        }
+
+        if dsp.cfar.min_bins == 0 {
+            return invalid("dsp.cfar.min_bins", String::from("must be positive"));
+        }
+
+        if dsp.srp_azimuth_steps == 0 || dsp.srp_elevation_steps == 0 {
+            return invalid(
+                "dsp.srp_azimuth_steps",
+                String::from("and dsp.srp_elevation_steps must be positive"),
+            );
+        }
+
+        if dsp.music_sources == 0 {
+            return invalid("dsp.music_sources", String::from("must be positive"));
+        }
+
+        let tracking = &self.tracking;
+        if !(tracking.process_noise > 0.0 && tracking.measurement_noise > 0.0) {
+            return invalid(
+                "tracking.process_noise",
+                String::from("and tracking.measurement_noise must be positive"),
+            );
+        }
+
+        if !(tracking.gate > 0.0 && tracking.gate.is_finite()) {
+            return invalid(
+                "tracking.gate",
+                format!("must be positive, found {}", tracking.gate),
+            );
+        }
+
+        let (m, n) = tracking.birth;
+        if m < 1 || m > n {
+            return invalid(
+                "tracking.birth",
+                format!("needs 1 <= m <= n, found [{m}, {n}]"),
+            );
+        }
+
+        if !(tracking.max_coast > 0.0 && tracking.max_coast.is_finite()) {
+            return invalid(
+                "tracking.max_coast",
+                format!("must be positive, found {}", tracking.max_coast),
+            );
+        }
+
+        if !(tracking.post_filter_cutoff > 0.0 && tracking.post_filter_cutoff.is_finite()) {
+            return invalid(
+                "tracking.post_filter_cutoff",
+                format!("must be positive, found {}", tracking.post_filter_cutoff),
+            );
+        }
+
+        match self.network.server.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => {
+                return invalid(
+                    "network.server",
+                    format!("must be host:port, found {:?}", self.network.server),
+                );
+            }
+        }
+
+        if self.ui.history_len == 0 {
+            return invalid("ui.history_len", String::from("must be positive"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets the dotted `key` of a `key=value` override, creating the sections on the way.
+fn set_override(table: &mut Table, item: &str) -> Result<()> {
+    let Some((key, value)) = item.split_once('=') else {
+        return Err(Error::Config(format!(
+            "override {item:?} isn't of the form key=value"
+        )));
+    };
+
+    let value = value.trim();
+    let value = format!("value = {value}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut x| x.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()));
+
+    let mut keys: Vec<&str> = key.trim().split('.').collect();
+    let last = keys.pop().unwrap_or_default();
+    if last.is_empty() || keys.iter().any(|x| x.is_empty()) {
+        return Err(Error::Config(format!("override {item:?} has an empty key")));
+    }
+
+    let mut section = table;
+    for name in keys {
+        section = section
+            .entry(name)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| Error::Config(format!("override {item:?}: {name} isn't a section")))?;
+    }
+
+    section.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Message of a configuration error, panics on success or any other error.
+    fn message<T: std::fmt::Debug>(result: Result<T>) -> String {
+        match result {
+            Err(Error::Config(message)) => message,
+            other => panic!("expected a configuration error, found {other:?}"),
+        }
+    }
+
+    const NODE: &str = "[node]\nh = 0.0\nk = 0.0\nphi = 0.0\n";
+
+    /// Parses `contents` after the required node section.
+    fn with_node(contents: &str) -> Result<Config> {
+        Config::parse(&format!("{NODE}{contents}"))
+    }
+
+    fn overridden(overrides: &[&str]) -> Result<Config> {
+        let overrides: Vec<String> = ["node.h=0.0", "node.k=0.0", "node.phi=0.0"]
+            .iter()
+            .chain(overrides)
+            .map(|x| x.to_string())
+            .collect();
+        Config::load_with_overrides(None, &overrides)
+    }
+
+    #[test]
+    fn node_alone_gives_the_defaults() {
+        let config = with_node("").unwrap();
+        assert_eq!(config.dsp.frame_len, DspSettings::default().frame_len);
+        assert!(!config.dsp.cfar.gating);
+        assert_eq!(config.array.geometry().unwrap().reference_pair(), (0, 1));
+    }
+
+    #[test]
+    fn example_file_lists_the_defaults() {
+        let config = Config::parse(include_str!("../voice_direction_finder.example.toml")).unwrap();
+        assert_eq!(config.dsp.frame_len, DspSettings::default().frame_len);
+        assert_eq!(
+            config.tracking.max_coast,
+            TrackingSettings::default().max_coast
+        );
+        assert_eq!(config.network.server, NetworkSettings::default().server);
+    }
+
+    #[test]
+    fn requires_the_node_pose() {
+        assert!(message(Config::parse("")).contains("node"));
+        assert!(message(Config::parse("[node]\nh = 1.0\nk = 2.0")).contains("phi"));
+        assert!(message(Config::load_with_overrides(None, &[])).contains("node"));
+    }
+
+    #[test]
+    fn validates_the_geometry() {
+        let duplicate = r#"
+            [array]
+            microphones = [
+                { channel = 0, position = [0.0, 0.0, 0.0] },
+                { channel = 0, position = [0.1, 0.0, 0.0] },
+            ]
+            "#;
+        assert!(message(with_node(duplicate)).contains("array"));
+
+        let missing = "[array]\ngeometry_file = \"/nonexistent/geometry.csv\"";
+        assert!(matches!(with_node(missing), Err(Error::Io { .. })));
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let config = Config::parse(
+            r#"
+            [node]
+            h = 1.5
+            k = -2.0
+            phi = 0.5
+            [array]
+            microphones = [
+                { channel = 0, position = [0.0, 0.0, 0.0] },
+                { channel = 2, position = [0.1, 0.0, 0.0] },
+            ]
+            [dsp]
+            window = { kaiser = 8.0 }
+            weighting = { phat_beta = 0.7 }
+            voice_band = [200.0, 3000.0]
+            [dsp.vad]
+            method = "cfar_count"
+            min_bins = 5
+            [tracking]
+            birth = [2, 4]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            (config.node.h, config.node.k, config.node.phi),
+            (1.5, -2.0, 0.5)
+        );
+        assert_eq!(config.array.geometry().unwrap().reference_pair(), (0, 2));
+        assert_eq!(config.dsp.window, Window::Kaiser(8.0));
+        assert_eq!(config.dsp.weighting, Weighting::PhatBeta(0.7));
+        assert_eq!(config.dsp.voice_band, Some((200.0, 3000.0)));
+        assert_eq!(config.dsp.vad, VadMethod::CfarCount { min_bins: 5 });
+        assert_eq!(config.tracking.birth, (2, 4));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(message(with_node("[dsp]\nweigting = \"scot\"")).contains("weigting"));
+        assert!(message(with_node("[dps]\nframe_len = 512")).contains("dps"));
+        assert!(message(with_node("[dsp.vad]\nmethod = \"loudness\"")).contains("loudness"));
+        assert!(message(Config::parse(&format!("{NODE}x = 1.0"))).contains('x'));
+        assert!(message(overridden(&["dsp.cfar.threshold=3"])).contains("threshold"));
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        for (contents, key) in [
+            ("[dsp]\nframe_len = \"big\"", "frame_len"),
+            ("[dsp]\nweighting = \"gcc\"", "gcc"),
+            ("[array]\nmic_distance = -0.2", "array.mic_distance"),
+            ("[array]\nmic_distance = nan", "array.mic_distance"),
+            ("[dsp]\nframe_len = 512\nframe_hop = 1024", "dsp.frame_hop"),
+            ("[dsp]\nvoice_band = [4000.0, 300.0]", "dsp.voice_band"),
+            ("[dsp.cfar]\nmin_bins = 0", "dsp.cfar.min_bins"),
+            ("[tracking]\nbirth = [4, 3]", "tracking.birth"),
+            ("[tracking]\nmax_coast = 0.0", "tracking.max_coast"),
+            (
+                "[tracking]\npost_filter_cutoff = 0.0",
+                "tracking.post_filter_cutoff",
+            ),
+            ("[network]\nserver = \"10.0.0.1\"", "network.server"),
+            ("[network]\nserver = \"10.0.0.1:99999\"", "network.server"),
+            ("[ui]\nhistory_len = 0", "ui.history_len"),
+        ] {
+            let message = message(with_node(contents));
+            assert!(message.contains(key), "{contents:?}: {message}");
+        }
+    }
+
+    #[test]
+    fn overrides_take_toml_values() {
+        let config = overridden(&[
+            "dsp.frame_len=2048",
+            " dsp.frame_hop = 1024 ",
+            "dsp.weighting=\"scot\"",
+            "tracking.birth=[2, 4]",
+            "dsp.cfar.gating=true",
+        ])
+        .unwrap();
+
+        assert_eq!(config.dsp.frame_len, 2048);
+        assert_eq!(config.dsp.frame_hop, 1024);
+        assert_eq!(config.dsp.weighting, Weighting::Scot);
+        assert_eq!(config.tracking.birth, (2, 4));
+        assert!(config.dsp.cfar.gating);
+    }
+
+    #[test]
+    fn overrides_fall_back_to_strings() {
+        let config = overridden(&["capture.device=USB Audio", "dsp.weighting=roth"]).unwrap();
+        assert_eq!(config.capture.device, "USB Audio");
+        assert_eq!(config.dsp.weighting, Weighting::Roth);
+
+        // a string where a number belongs is still an error
+        assert!(message(overridden(&["dsp.frame_len=large"])).contains("frame_len"));
+    }
+
+    #[test]
+    fn later_overrides_win() {
+        let config = overridden(&["ui.history_len=10", "ui.history_len=20"]).unwrap();
+        assert_eq!(config.ui.history_len, 20);
+    }
+
+    #[test]
+    fn rejects_malformed_overrides() {
+        assert!(message(overridden(&["dsp.frame_len"])).contains("key=value"));
+        assert!(message(overridden(&["=3"])).contains("empty key"));
+        assert!(message(overridden(&["dsp..frame_len=3"])).contains("empty key"));
+        assert!(message(overridden(&["dsp.=3"])).contains("empty key"));
+        assert!(message(overridden(&["dsp.frame_len.x=3"])).contains("frame_len"));
+        assert!(
+            message(overridden(&["dsp.frame_len=1024", "dsp.frame_len.x=3"]))
+                .contains("frame_len isn't a section")
+        );
+        assert!(message(overridden(&["dsp.frame_hop=0"])).contains("dsp.frame_hop"));
+    }
+}
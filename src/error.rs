@@ -0,0 +1,57 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong in the crate, grouped by where it happened so the binary
+/// can tell operators what to fix.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid parameters, geometry or settings.
+    Config(String),
+    /// Finding, querying or configuring an audio device.
+    Device(String),
+    /// Building, starting or reading an audio stream or recording.
+    Stream(String),
+    /// A frame the signal processing couldn't produce an estimate from.
+    Dsp(&'static str),
+    /// Sending results to the TCP server.
+    Network(io::Error),
+    /// Reading or writing the file at `path`.
+    Io { path: String, source: io::Error },
+    /// The window couldn't be opened.
+    Gui(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Wraps an I/O error with the path it happened on.
+    pub fn io(path: &str, source: io::Error) -> Self {
+        Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "invalid configuration: {message}"),
+            Error::Device(message) => write!(f, "audio device: {message}"),
+            Error::Stream(message) => write!(f, "audio stream: {message}"),
+            Error::Dsp(message) => write!(f, "signal processing: {message}"),
+            Error::Network(source) => write!(f, "network: {source}"),
+            Error::Io { path, source } => write!(f, "{path}: {source}"),
+            Error::Gui(message) => write!(f, "window: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(source) | Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
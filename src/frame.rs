@@ -0,0 +1,235 @@
+use crate::error::{Error, Result};
+use rustfft::num_complex::Complex32;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::fmt;
+
+/// Analysis window applied to every frame before the FFT.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    /// Kaiser window with the given `beta`, larger values trade resolution for lower sidelobes.
+    Kaiser(f32),
+}
+
+impl Window {
+    /// Periodic window coefficients of length `len`, as used for overlapping STFT frames.
+    pub fn coefficients(&self, len: usize) -> Vec<f32> {
+        let n = len as f32;
+
+        (0..len)
+            .map(|i| {
+                let x = 2.0 * PI * i as f32 / n;
+                match self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * x.cos(),
+                    Window::Hamming => 0.54 - 0.46 * x.cos(),
+                    Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+                    Window::Kaiser(beta) => {
+                        let ratio = 2.0 * i as f32 / n - 1.0;
+                        bessel_i0(beta * (1.0 - ratio * ratio).sqrt()) / bessel_i0(*beta)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Window::Rectangular => write!(f, "Rectangular"),
+            Window::Hann => write!(f, "Hann"),
+            Window::Hamming => write!(f, "Hamming"),
+            Window::Blackman => write!(f, "Blackman"),
+            Window::Kaiser(beta) => write!(f, "Kaiser ({beta})"),
+        }
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind, by its power series.
+fn bessel_i0(x: f32) -> f32 {
+    let half = x / 2.0;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+
+    for k in 1..50 {
+        term *= (half / k as f32) * (half / k as f32);
+        sum += term;
+        if term < sum * 1e-9 {
+            break;
+        }
+    }
+
+    sum
+}
+
+/// Buffers the samples of one channel into fixed size, overlapping, windowed frames.
+///
+/// The audio driver decides how many samples every block has, framing makes the FFT size
+/// and frequency resolution independent of it.
+pub struct Framer {
+    frame_len: usize,
+    hop: usize,
+    window: Vec<f32>,
+    buffer: VecDeque<Complex32>,
+}
+
+impl Framer {
+    pub fn new(frame_len: usize, hop: usize, window: Window) -> Result<Self> {
+        if frame_len == 0 {
+            return Err(Error::Config(String::from("Frame length must be non zero")));
+        }
+        if hop == 0 || hop > frame_len {
+            return Err(Error::Config(format!(
+                "Hop must be between 1 and the frame length {frame_len}, found {hop}"
+            )));
+        }
+
+        Ok(Framer {
+            frame_len,
+            hop,
+            window: window.coefficients(frame_len),
+            buffer: VecDeque::with_capacity(2 * frame_len),
+        })
+    }
+
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// Appends a block of samples and returns every frame that became complete, in order.
+    pub fn push(&mut self, samples: &[Complex32]) -> Vec<Vec<Complex32>> {
+        self.buffer.extend(samples.iter().copied());
+
+        let mut frames = Vec::new();
+
+        while self.buffer.len() >= self.frame_len {
+            frames.push(
+                self.buffer
+                    .iter()
+                    .zip(self.window.iter())
+                    .map(|(x, w)| x * w)
+                    .collect(),
+            );
+
+            self.buffer.drain(..self.hop);
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(range: std::ops::Range<usize>) -> Vec<Complex32> {
+        range.map(|i| Complex32::new(i as f32, 0.0)).collect()
+    }
+
+    #[test]
+    fn frames_follow_the_hop_whatever_the_block_size() {
+        let samples = ramp(0..10_000);
+
+        for block in [1, 7, 256, 1000, 4096] {
+            let mut framer = Framer::new(1024, 256, Window::Rectangular).unwrap();
+            let frames: Vec<Vec<Complex32>> = samples
+                .chunks(block)
+                .flat_map(|chunk| framer.push(chunk))
+                .collect();
+
+            assert_eq!(frames.len(), (10_000 - 1024) / 256 + 1, "{block}");
+            for (k, frame) in frames.iter().enumerate() {
+                assert_eq!(frame, &ramp(k * 256..k * 256 + 1024), "{block}");
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_frames_share_their_samples() {
+        let mut framer = Framer::new(8, 2, Window::Rectangular).unwrap();
+        assert!(framer.push(&ramp(0..7)).is_empty());
+
+        let frames = framer.push(&ramp(7..12));
+        assert_eq!(frames.len(), 3);
+        // three quarters of every frame is the tail of the one before
+        for pair in frames.windows(2) {
+            assert_eq!(pair[0][2..], pair[1][..6]);
+        }
+        assert_eq!(frames[2], ramp(4..12));
+    }
+
+    #[test]
+    fn frames_are_windowed() {
+        let mut framer = Framer::new(16, 16, Window::Hann).unwrap();
+        let frames = framer.push(&[Complex32::new(2.0, 0.0); 16]);
+
+        let window = Window::Hann.coefficients(16);
+        let expected: Vec<Complex32> = window
+            .iter()
+            .map(|w| Complex32::new(2.0 * w, 0.0))
+            .collect();
+        assert_eq!(frames, [expected]);
+    }
+
+    #[test]
+    fn rejects_invalid_hops() {
+        assert!(matches!(
+            Framer::new(1024, 1025, Window::Hann),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            Framer::new(1024, 0, Window::Hann),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            Framer::new(0, 0, Window::Hann),
+            Err(Error::Config(_))
+        ));
+        assert!(Framer::new(1024, 1024, Window::Hann).is_ok());
+    }
+
+    #[test]
+    fn hann_is_periodic_with_its_peak_in_the_middle() {
+        let window = Window::Hann.coefficients(64);
+        assert_eq!(window[0], 0.0);
+        assert!((window[32] - 1.0).abs() < 1e-6, "{}", window[32]);
+        // periodic, so the last point is one step short of zero again
+        assert!(window[63] > 0.0);
+        for i in 1..64 {
+            assert!((window[i] - window[64 - i]).abs() < 1e-6, "{i}");
+        }
+    }
+
+    #[test]
+    fn kaiser_is_symmetric_and_tapers_with_beta() {
+        for beta in [0.0, 4.0, 8.6] {
+            let window = Window::Kaiser(beta).coefficients(64);
+            assert!((window[32] - 1.0).abs() < 1e-6, "{beta}");
+            for i in 1..64 {
+                assert!((window[i] - window[64 - i]).abs() < 1e-5, "{beta} {i}");
+            }
+            let edge = 1.0 / bessel_i0(beta);
+            assert!((window[0] - edge).abs() < 1e-6, "{beta}");
+        }
+
+        // beta 0 is rectangular, larger betas taper further
+        assert!(
+            Window::Kaiser(0.0)
+                .coefficients(8)
+                .iter()
+                .all(|w| (w - 1.0).abs() < 1e-6)
+        );
+        assert!(Window::Kaiser(8.6).coefficients(8)[0] < Window::Kaiser(4.0).coefficients(8)[0]);
+    }
+}
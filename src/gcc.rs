@@ -0,0 +1,807 @@
+use crate::SPEED_SOUND;
+use crate::error::{Error, Result};
+use crate::geometry::ArrayGeometry;
+use crate::signal::{PeakSearch, SignalProcessor};
+use rustfft::num_complex::Complex32;
+use serde::Deserialize;
+use std::fmt;
+
+/// Frequency weighting applied to the cross power spectrum before the inverse FFT.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Weighting {
+    /// Plain cross-correlation, every bin keeps its magnitude.
+    CrossCorrelation,
+    /// Phase transform, every bin is normalized to unit magnitude.
+    Phat,
+    /// PHAT with the magnitude raised to `beta`, `0.0` is plain cross-correlation and `1.0` PHAT.
+    PhatBeta(f32),
+    /// Smoothed coherence transform, `1 / sqrt(G11 * G22)`.
+    Scot,
+    /// Roth processor, `1 / G11` using the left channel as reference.
+    Roth,
+    /// Hannan-Thomson maximum likelihood weighting, `|γ|² / (|G12| (1 - |γ|²))`.
+    HannanThomson,
+}
+
+impl Weighting {
+    pub const ALL: [Weighting; 6] = [
+        Weighting::CrossCorrelation,
+        Weighting::Phat,
+        Weighting::PhatBeta(0.7),
+        Weighting::Scot,
+        Weighting::Roth,
+        Weighting::HannanThomson,
+    ];
+}
+
+impl fmt::Display for Weighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Weighting::CrossCorrelation => write!(f, "CC"),
+            Weighting::Phat => write!(f, "PHAT"),
+            Weighting::PhatBeta(beta) => write!(f, "PHAT-β ({beta:.2})"),
+            Weighting::Scot => write!(f, "SCOT"),
+            Weighting::Roth => write!(f, "ROTH"),
+            Weighting::HannanThomson => write!(f, "HT"),
+        }
+    }
+}
+
+/// Largest delay two microphones `mic_dis` metres apart can observe, widened by the
+/// fractional `margin` to absorb spacing and sound speed errors.
+pub fn max_lag(mic_dis: f64, margin: f64) -> f32 {
+    (mic_dis * (1.0 + margin) / SPEED_SOUND) as f32
+}
+
+/// Shape of the correlation peak, telling a crisp peak from a flat correlation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PeakQuality {
+    /// Interpolated height of the correlation peak.
+    pub peak: f32,
+    /// Peak height over the highest other local maximum in the searched lags.
+    pub peak_to_second: f32,
+    /// Peak height over the mean absolute correlation in the searched lags.
+    pub peak_to_mean: f32,
+    /// Curvature of the peak relative to its height, `(2 y - y_left - y_right) / y`.
+    pub sharpness: f32,
+}
+
+impl PeakQuality {
+    /// Measures the peak of `correlation` at `delay` seconds, only looking at lags within
+    /// `±max_lag` when one is given.
+    pub fn measure(
+        correlation: &[(f32, f32)],
+        delay: f32,
+        peak: f32,
+        max_lag: Option<f32>,
+    ) -> Self {
+        let in_window = |t: f32| max_lag.is_none_or(|max_lag| t.abs() <= max_lag);
+
+        let Some(center) = correlation
+            .iter()
+            .enumerate()
+            .min_by(|(_, (a, _)), (_, (b, _))| (a - delay).abs().total_cmp(&(b - delay).abs()))
+            .map(|(i, _)| i)
+        else {
+            return PeakQuality::default();
+        };
+
+        // walk down both sides of the main lobe so its own slope isn't taken as a second peak
+        let mut lobe_start = center;
+        while lobe_start > 0 && correlation[lobe_start - 1].1 < correlation[lobe_start].1 {
+            lobe_start -= 1;
+        }
+        let mut lobe_end = center;
+        while lobe_end + 1 < correlation.len()
+            && correlation[lobe_end + 1].1 < correlation[lobe_end].1
+        {
+            lobe_end += 1;
+        }
+
+        let second = (1..correlation.len().saturating_sub(1))
+            .filter(|i| *i < lobe_start || *i > lobe_end)
+            .filter(|i| in_window(correlation[*i].0))
+            .map(|i| (correlation[i - 1].1, correlation[i].1, correlation[i + 1].1))
+            .filter(|(left, y, right)| y >= left && y >= right)
+            .map(|(_, y, _)| y)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        let window: Vec<f32> = correlation
+            .iter()
+            .filter(|(t, _)| in_window(*t))
+            .map(|(_, y)| y.abs())
+            .collect();
+        let mean = window.iter().sum::<f32>() / window.len().max(1) as f32;
+
+        let sharpness = if center > 0 && center + 1 < correlation.len() && peak > 0.0 {
+            (2.0 * correlation[center].1 - correlation[center - 1].1 - correlation[center + 1].1)
+                / peak
+        } else {
+            0.0
+        };
+
+        PeakQuality {
+            peak,
+            peak_to_second: peak / second.max(1e-6),
+            peak_to_mean: peak / mean.max(1e-6),
+            sharpness,
+        }
+    }
+}
+
+/// Time difference of arrival found by [`GccEstimator`].
+pub struct TdoaEstimate {
+    /// Delay in seconds, positive when the left channel hears the source after the right one.
+    pub delay: f32,
+    pub quality: PeakQuality,
+    /// Peak height relative to the largest value the weighted spectrum of the band could reach
+    /// without the detection mask, clamped to `0.0..=1.0`. This is the fraction of the band
+    /// that agrees on the delay, bins dropped by the mask count as disagreeing.
+    pub confidence: f32,
+    /// `(lag in seconds, correlation)` pairs centred on zero lag, as `fft_time_addition` returns.
+    pub correlation: Vec<(f32, f32)>,
+    /// Every peak of the correlation found by [`SignalProcessor::find_peaks`] as
+    /// `(delay, correlation)`, strongest first. More than one means several sources.
+    pub peaks: Vec<(f32, f32)>,
+    /// Quality of every peak in [`Self::peaks`], in the same order.
+    pub peak_qualities: Vec<PeakQuality>,
+}
+
+/// Generalized cross-correlation time delay estimator for one pair of channels.
+///
+/// SCOT, ROTH and Hannan-Thomson need auto and cross spectra averaged over several blocks,
+/// the estimator keeps them with exponential smoothing across calls.
+pub struct GccEstimator {
+    processor: SignalProcessor,
+    weighting: Weighting,
+    band: Option<(f32, f32)>,
+    max_lag: Option<f32>,
+    min_bins: usize,
+    peak_search: PeakSearch,
+    smoothing: f32,
+    auto_left: Vec<f32>,
+    auto_right: Vec<f32>,
+    cross: Vec<Complex32>,
+}
+
+impl GccEstimator {
+    pub fn new(samples_rate: u32) -> Self {
+        GccEstimator {
+            processor: SignalProcessor::new(samples_rate),
+            weighting: Weighting::Phat,
+            band: None,
+            max_lag: None,
+            min_bins: 1,
+            peak_search: PeakSearch::default(),
+            smoothing: 0.8,
+            auto_left: Vec::new(),
+            auto_right: Vec::new(),
+            cross: Vec::new(),
+        }
+    }
+
+    pub fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+
+    /// Keeps only the cross spectrum bins between `low` and `high` Hz, `None` keeps every bin.
+    pub fn set_band(&mut self, band: Option<(f32, f32)>) {
+        self.band = band;
+    }
+
+    /// Restricts the peak search to `±max_lag` seconds, see [`max_lag`]. `None` searches
+    /// every lag of the correlation.
+    pub fn set_max_lag(&mut self, max_lag: Option<f32>) {
+        self.max_lag = max_lag;
+    }
+
+    /// Fewest bins up to the Nyquist frequency the band and the detection mask may leave, a
+    /// correlation of fewer bins is a cosine whose peak says nothing about the delay.
+    pub fn set_min_bins(&mut self, min_bins: usize) {
+        self.min_bins = min_bins.max(1);
+    }
+
+    /// How the secondary peaks of [`TdoaEstimate::peaks`] are picked.
+    pub fn set_peak_search(&mut self, peak_search: PeakSearch) {
+        self.peak_search = peak_search;
+    }
+
+    /// Sets how much of the previous smoothed spectra is kept per block, in `0.0..1.0`.
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.clamp(0.0, 0.999);
+    }
+
+    /// Estimates the delay between two time domain blocks of equal length.
+    pub fn estimate(&mut self, left: &[Complex32], right: &[Complex32]) -> Result<TdoaEstimate> {
+        let left_fft = self.processor.fft(&mut left.to_vec());
+        let right_fft = self.processor.fft(&mut right.to_vec());
+
+        self.estimate_from_spectra(&left_fft, &right_fft, None)
+    }
+
+    /// Estimates the delay from spectra already computed with [`SignalProcessor::fft`].
+    ///
+    /// `detected` optionally marks the bins worth keeping, for example from
+    /// [`cfar_detections`](crate::cfar_detections), every other bin is zeroed before the
+    /// inverse FFT together with the bins outside the band set by [`Self::set_band`]. Fails
+    /// when fewer bins than [`Self::set_min_bins`] are left.
+    pub fn estimate_from_spectra(
+        &mut self,
+        left_fft: &[Complex32],
+        right_fft: &[Complex32],
+        detected: Option<&[bool]>,
+    ) -> Result<TdoaEstimate> {
+        let mut cross_spectrum = self.cross_spectrum(left_fft, right_fft)?;
+
+        // the correlation can't exceed the mean weighted magnitude, which makes the peak
+        // comparable between weightings. It is taken before the detection mask, so a
+        // correlation of the few bins left by the mask can't reach full confidence
+        self.apply_mask(&mut cross_spectrum, None)?;
+        let reachable =
+            cross_spectrum.iter().map(|x| x.norm()).sum::<f32>() / cross_spectrum.len() as f32;
+
+        let kept = self.apply_mask(&mut cross_spectrum, detected)?;
+        if kept < self.min_bins {
+            return Err(Error::Dsp("Too few bins left after masking"));
+        }
+
+        let correlation = self.processor.ifft(&mut cross_spectrum); // this part is gcc phat
+        let correlation = self.processor.fft_time_addition(&correlation);
+
+        let (delay, peak_value) = match self.max_lag {
+            Some(max_lag) => self
+                .processor
+                .parabolic_interpolate_peak_in_window(&correlation, max_lag)?,
+            None => self
+                .processor
+                .parabolic_interpolate_peak_robust(&correlation)?,
+        };
+
+        let peaks = self
+            .processor
+            .find_peaks(&correlation, &self.peak_search, self.max_lag);
+        let peak_qualities = peaks
+            .iter()
+            .map(|(delay, peak)| PeakQuality::measure(&correlation, *delay, *peak, self.max_lag))
+            .collect();
+
+        Ok(TdoaEstimate {
+            delay,
+            quality: PeakQuality::measure(&correlation, delay, peak_value, self.max_lag),
+            confidence: if reachable > 0.0 {
+                (peak_value / reachable).clamp(0.0, 1.0)
+            } else {
+                0.0
+            },
+            correlation,
+            peaks,
+            peak_qualities,
+        })
+    }
+
+    /// Cross power spectrum `L * conj(R)` weighted with the current [`Weighting`].
+    pub fn cross_spectrum(
+        &mut self,
+        left_fft: &[Complex32],
+        right_fft: &[Complex32],
+    ) -> Result<Vec<Complex32>> {
+        if left_fft.len() != right_fft.len() {
+            return Err(Error::Dsp("Spectra have different lengths"));
+        }
+
+        let cross: Vec<Complex32> = left_fft
+            .iter()
+            .zip(right_fft.iter().map(|x| x.conj()))
+            .map(|(x, y)| x * y)
+            .collect();
+
+        self.update_smoothed_spectra(left_fft, right_fft, &cross);
+
+        Ok(cross
+            .iter()
+            .enumerate()
+            .map(|(i, product)| {
+                let weight = self.weight(i, product);
+                if weight.is_finite() {
+                    product * weight
+                } else {
+                    Complex32::new(0.0, 0.0)
+                }
+            })
+            .collect())
+    }
+
+    /// Zeroes the bins outside the band or the detections and returns how many bins up to
+    /// the Nyquist frequency are left.
+    fn apply_mask(
+        &self,
+        cross_spectrum: &mut [Complex32],
+        detected: Option<&[bool]>,
+    ) -> Result<usize> {
+        let n = cross_spectrum.len();
+
+        if let Some(detected) = detected
+            && detected.len() != n
+        {
+            return Err(Error::Dsp(
+                "Detection mask has a different length than the spectrum",
+            ));
+        }
+
+        let resolution = self.processor.get_fft_frequency_resolution(n);
+        let mut kept = 0;
+
+        for (i, x) in cross_spectrum.iter_mut().enumerate() {
+            // bin i and n - i are the same frequency of a real signal, the mask is kept
+            // symmetric so the correlation stays real
+            let mirror = (n - i) % n;
+            let frequency = i.min(mirror) as f32 * resolution;
+
+            let in_band = self
+                .band
+                .is_none_or(|(low, high)| frequency >= low && frequency <= high);
+            let is_detected = detected.is_none_or(|detected| detected[i] || detected[mirror]);
+
+            if !(in_band && is_detected) {
+                *x = Complex32::new(0.0, 0.0);
+            } else if i <= n / 2 {
+                kept += 1;
+            }
+        }
+
+        Ok(kept)
+    }
+
+    fn update_smoothed_spectra(
+        &mut self,
+        left_fft: &[Complex32],
+        right_fft: &[Complex32],
+        cross: &[Complex32],
+    ) {
+        // kept up to date for every weighting so switching at runtime starts from a warm average
+        if self.cross.len() != cross.len() {
+            // first block or the block size changed, start averaging again
+            self.auto_left = left_fft.iter().map(|x| x.norm_sqr()).collect();
+            self.auto_right = right_fft.iter().map(|x| x.norm_sqr()).collect();
+            self.cross = cross.to_vec();
+            return;
+        }
+
+        let alpha = self.smoothing;
+
+        for i in 0..cross.len() {
+            self.auto_left[i] = alpha * self.auto_left[i] + (1.0 - alpha) * left_fft[i].norm_sqr();
+            self.auto_right[i] =
+                alpha * self.auto_right[i] + (1.0 - alpha) * right_fft[i].norm_sqr();
+            self.cross[i] = self.cross[i] * alpha + cross[i] * (1.0 - alpha);
+        }
+    }
+
+    fn weight(&self, bin: usize, product: &Complex32) -> f32 {
+        match self.weighting {
+            Weighting::CrossCorrelation => 1.0,
+            // for gcc phat, you have to divide the magnetude to make it "unity"
+            Weighting::Phat => 1.0 / product.norm(),
+            Weighting::PhatBeta(beta) => 1.0 / product.norm().powf(beta),
+            Weighting::Scot => 1.0 / (self.auto_left[bin] * self.auto_right[bin]).sqrt(),
+            Weighting::Roth => 1.0 / self.auto_left[bin],
+            Weighting::HannanThomson => {
+                let auto = self.auto_left[bin] * self.auto_right[bin];
+                let coherence = (self.cross[bin].norm_sqr() / auto).min(0.999);
+                coherence / (product.norm() * (1.0 - coherence))
+            }
+        }
+    }
+}
+
+/// Delay estimates between every pair of microphones, filled by [`PairwiseGcc`].
+pub struct TdoaMatrix {
+    channels: usize,
+    pairs: Vec<(usize, usize)>,
+    estimates: Vec<Option<TdoaEstimate>>,
+}
+
+impl TdoaMatrix {
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Estimate of the pair `(i, j)` with `i < j`, channel `i` playing the left role.
+    pub fn get(&self, i: usize, j: usize) -> Option<&TdoaEstimate> {
+        self.estimates[pair_index(&self.pairs, i, j)?].as_ref()
+    }
+
+    /// Takes the estimate of the pair `(i, j)` with `i < j` out of the matrix.
+    pub fn take(&mut self, i: usize, j: usize) -> Option<TdoaEstimate> {
+        self.estimates[pair_index(&self.pairs, i, j)?].take()
+    }
+
+    /// Seconds channel `i` hears the source after channel `j`, for any order of the pair.
+    pub fn delay(&self, i: usize, j: usize) -> Option<f32> {
+        match i.cmp(&j) {
+            std::cmp::Ordering::Less => self.get(i, j).map(|x| x.delay),
+            std::cmp::Ordering::Greater => self.get(j, i).map(|x| -x.delay),
+            std::cmp::Ordering::Equal => self
+                .pairs
+                .iter()
+                .any(|(a, b)| *a == i || *b == i)
+                .then_some(0.0),
+        }
+    }
+
+    /// Every pair `(i, j)` with `i < j` that produced an estimate.
+    pub fn pairs(&self) -> impl Iterator<Item = (usize, usize, &TdoaEstimate)> {
+        self.pairs
+            .iter()
+            .zip(self.estimates.iter())
+            .filter_map(|((i, j), estimate)| estimate.as_ref().map(|x| (*i, *j, x)))
+    }
+}
+
+/// One [`GccEstimator`] per pair of microphones of an array.
+pub struct PairwiseGcc {
+    channels: usize,
+    pairs: Vec<(usize, usize)>,
+    estimators: Vec<GccEstimator>,
+}
+
+impl PairwiseGcc {
+    /// Pairs every two microphones of `geometry`, input channels without a microphone are
+    /// never correlated.
+    pub fn new(samples_rate: u32, geometry: &ArrayGeometry) -> Self {
+        let mut channels: Vec<usize> = geometry.microphones().iter().map(|x| x.channel).collect();
+        channels.sort_unstable();
+
+        let pairs: Vec<(usize, usize)> = channels
+            .iter()
+            .enumerate()
+            .flat_map(|(n, i)| channels[n + 1..].iter().map(move |j| (*i, *j)))
+            .collect();
+
+        PairwiseGcc {
+            channels: geometry.required_channels(),
+            estimators: pairs
+                .iter()
+                .map(|_| GccEstimator::new(samples_rate))
+                .collect(),
+            pairs,
+        }
+    }
+
+    /// Channels the spectra passed to [`Self::estimate_from_spectra`] have to cover.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Channel pairs `(i, j)` with `i < j` that are estimated, in the order of the estimates.
+    pub fn pairs(&self) -> &[(usize, usize)] {
+        &self.pairs
+    }
+
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        for estimator in self.estimators.iter_mut() {
+            estimator.set_weighting(weighting);
+        }
+    }
+
+    pub fn set_band(&mut self, band: Option<(f32, f32)>) {
+        for estimator in self.estimators.iter_mut() {
+            estimator.set_band(band);
+        }
+    }
+
+    pub fn set_min_bins(&mut self, min_bins: usize) {
+        for estimator in self.estimators.iter_mut() {
+            estimator.set_min_bins(min_bins);
+        }
+    }
+
+    pub fn set_peak_search(&mut self, peak_search: PeakSearch) {
+        for estimator in self.estimators.iter_mut() {
+            estimator.set_peak_search(peak_search);
+        }
+    }
+
+    /// Lag window of the pair `(i, j)` with `i < j`, it depends on the spacing of that pair.
+    pub fn set_max_lag(&mut self, i: usize, j: usize, max_lag: Option<f32>) {
+        if let Some(index) = pair_index(&self.pairs, i, j) {
+            self.estimators[index].set_max_lag(max_lag);
+        }
+    }
+
+    /// Estimates every pair from one spectrum per channel, pairs that fail are left empty.
+    pub fn estimate_from_spectra(
+        &mut self,
+        spectra: &[Vec<Complex32>],
+        detected: Option<&[bool]>,
+    ) -> TdoaMatrix {
+        let estimates = self
+            .pairs
+            .iter()
+            .zip(self.estimators.iter_mut())
+            .map(|((i, j), estimator)| {
+                let (left, right) = (spectra.get(*i)?, spectra.get(*j)?);
+                estimator.estimate_from_spectra(left, right, detected).ok()
+            })
+            .collect();
+
+        TdoaMatrix {
+            channels: self.channels,
+            pairs: self.pairs.clone(),
+            estimates,
+        }
+    }
+}
+
+fn pair_index(pairs: &[(usize, usize)], i: usize, j: usize) -> Option<usize> {
+    pairs.iter().position(|pair| *pair == (i, j))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioSource;
+    use crate::cfar_detections;
+    use crate::frame::{Framer, Window};
+    use crate::generator::{SignalGenerator, SourceKind};
+
+    const RATE: u32 = 48000;
+
+    /// Hann windowed spectra of the left and right channel of `frames` frames.
+    fn spectra(mut generator: SignalGenerator, frames: usize) -> Vec<[Vec<Complex32>; 2]> {
+        let mut processor = SignalProcessor::new(RATE);
+        let mut framers = [
+            Framer::new(1024, 512, Window::Hann).unwrap(),
+            Framer::new(1024, 512, Window::Hann).unwrap(),
+        ];
+        let mut spectra = Vec::new();
+
+        while spectra.len() < frames {
+            let block = generator.next_block().unwrap();
+            let left = framers[0].push(&block[0]);
+            let right = framers[1].push(&block[1]);
+
+            for (mut left, mut right) in left.into_iter().zip(right) {
+                spectra.push([processor.fft(&mut left), processor.fft(&mut right)]);
+            }
+        }
+
+        spectra
+    }
+
+    fn median(mut delays: Vec<f32>) -> f32 {
+        assert!(!delays.is_empty(), "no estimates");
+        delays.sort_by(|a, b| a.total_cmp(b));
+        delays[delays.len() / 2]
+    }
+
+    #[test]
+    fn recovers_injected_delays() {
+        for delay in [-400e-6, -123.4e-6, 0.0, 57.3e-6, 200e-6, 480e-6] {
+            for weighting in Weighting::ALL {
+                let generator = SignalGenerator::new(RATE, 1024, SourceKind::WhiteNoise, delay)
+                    .unwrap()
+                    .with_noise(20.0);
+                let mut estimator = GccEstimator::new(RATE);
+                estimator.set_weighting(weighting);
+
+                let delays = spectra(generator, 20)
+                    .iter()
+                    .skip(4)
+                    .filter_map(|[left, right]| {
+                        estimator.estimate_from_spectra(left, right, None).ok()
+                    })
+                    .map(|x| x.delay)
+                    .collect();
+
+                let error = (median(delays) - delay).abs();
+                assert!(error < 5e-6, "{weighting} at {delay}: off by {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn lag_window_bounds_the_peak() {
+        let max_lag = max_lag(0.2, 0.1);
+
+        let mut estimator = GccEstimator::new(RATE);
+        estimator.set_max_lag(Some(max_lag));
+
+        // in the window the delay is found as without one
+        let generator = SignalGenerator::new(RATE, 1024, SourceKind::WhiteNoise, 300e-6).unwrap();
+        for [left, right] in spectra(generator, 8).iter().skip(2) {
+            let estimate = estimator.estimate_from_spectra(left, right, None).unwrap();
+            assert!((estimate.delay - 300e-6).abs() < 5e-6, "{}", estimate.delay);
+        }
+
+        // a delay no pair this close could observe is never reported
+        let generator = SignalGenerator::new(RATE, 1024, SourceKind::WhiteNoise, 2e-3).unwrap();
+        for [left, right] in spectra(generator, 8).iter().skip(2) {
+            if let Ok(estimate) = estimator.estimate_from_spectra(left, right, None) {
+                assert!(estimate.delay.abs() <= max_lag, "{}", estimate.delay);
+                assert!(estimate.peaks.iter().all(|(t, _)| t.abs() <= max_lag));
+            }
+        }
+    }
+
+    #[test]
+    fn peak_quality_measures_the_main_lobe() {
+        // a 0.1 floor, the main peak at zero lag, a lower one at 0.5 ms and a higher one
+        // at 2 ms
+        let correlation: Vec<(f32, f32)> = (-30..=30)
+            .map(|i| {
+                let y = match i {
+                    0 => 1.0,
+                    -1 | 1 => 0.6,
+                    5 => 0.5,
+                    20 => 0.8,
+                    _ => 0.1,
+                };
+                (i as f32 * 1e-4, y)
+            })
+            .collect();
+
+        let quality = PeakQuality::measure(&correlation, 0.0, 1.0, Some(1.05e-3));
+        assert_eq!(quality.peak, 1.0);
+        assert!((quality.peak_to_second - 2.0).abs() < 1e-5, "{quality:?}");
+        // 21 lags in the window adding up to 4.4
+        assert!(
+            (quality.peak_to_mean - 21.0 / 4.4).abs() < 1e-4,
+            "{quality:?}"
+        );
+        assert!((quality.sharpness - 0.8).abs() < 1e-5, "{quality:?}");
+
+        // without the window the peak at 2 ms is the second one
+        let quality = PeakQuality::measure(&correlation, 0.0, 1.0, None);
+        assert!((quality.peak_to_second - 1.25).abs() < 1e-5, "{quality:?}");
+
+        assert_eq!(
+            PeakQuality::measure(&[], 0.0, 1.0, None),
+            PeakQuality::default()
+        );
+    }
+
+    #[test]
+    fn every_peak_has_a_quality() {
+        let mut estimator = GccEstimator::new(RATE);
+        let generator = SignalGenerator::new(RATE, 1024, SourceKind::WhiteNoise, 300e-6).unwrap();
+
+        for [left, right] in spectra(generator, 8).iter().skip(2) {
+            let estimate = estimator.estimate_from_spectra(left, right, None).unwrap();
+            assert_eq!(estimate.peak_qualities.len(), estimate.peaks.len());
+            for ((delay, peak), quality) in estimate.peaks.iter().zip(&estimate.peak_qualities) {
+                assert_eq!(
+                    *quality,
+                    PeakQuality::measure(&estimate.correlation, *delay, *peak, None)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pairs_follow_the_microphones_of_the_geometry() {
+        use crate::geometry::Microphone;
+
+        // channels 1, 3 and 4 of the input have no microphone
+        let geometry = ArrayGeometry::new(
+            [(5, 0.1), (0, -0.1), (2, 0.0)]
+                .into_iter()
+                .map(|(channel, x)| Microphone {
+                    channel,
+                    position: [x, 0.0, 0.0],
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let mut estimator = PairwiseGcc::new(RATE, &geometry);
+        assert_eq!(estimator.channels(), 6);
+        assert_eq!(estimator.pairs(), [(0, 2), (0, 5), (2, 5)]);
+
+        let generator = SignalGenerator::new(RATE, 1024, SourceKind::WhiteNoise, 200e-6).unwrap();
+        let [left, right] = spectra(generator, 3).pop().unwrap();
+        let spectra = vec![
+            left.clone(),
+            left,
+            right.clone(),
+            right.clone(),
+            right.clone(),
+            right,
+        ];
+
+        let matrix = estimator.estimate_from_spectra(&spectra, None);
+        let pairs: Vec<(usize, usize)> = matrix.pairs().map(|(i, j, _)| (i, j)).collect();
+        assert_eq!(pairs, [(0, 2), (0, 5), (2, 5)]);
+        assert!(matrix.get(0, 1).is_none());
+        assert!(matrix.delay(1, 1).is_none());
+        assert!((matrix.delay(0, 5).unwrap() - 200e-6).abs() < 5e-6);
+        assert!((matrix.delay(5, 0).unwrap() + 200e-6).abs() < 5e-6);
+    }
+
+    #[test]
+    fn band_mask_keeps_the_band_symmetric() {
+        let n = 1024;
+        let mut estimator = GccEstimator::new(RATE);
+        estimator.set_band(Some((300.0, 4000.0)));
+
+        let mut spectrum = vec![Complex32::new(1.0, 0.0); n];
+        let kept = estimator.apply_mask(&mut spectrum, None).unwrap();
+
+        let resolution = RATE as f32 / n as f32;
+        let in_band = |i: usize| {
+            let frequency = i.min(n - i) as f32 * resolution;
+            (300.0..=4000.0).contains(&frequency)
+        };
+
+        assert_eq!(kept, (0..=n / 2).filter(|i| in_band(*i)).count());
+        for (i, x) in spectrum.iter().enumerate() {
+            assert_eq!(x.re != 0.0, in_band(i % n), "bin {i}");
+        }
+    }
+
+    #[test]
+    fn band_limited_speech_is_recovered() {
+        let generator = SignalGenerator::new(RATE, 1024, SourceKind::SpeechNoise, 200e-6)
+            .unwrap()
+            .with_noise(20.0);
+        let mut estimator = GccEstimator::new(RATE);
+        estimator.set_band(Some((300.0, 4000.0)));
+        estimator.set_max_lag(Some(max_lag(0.2, 0.1)));
+
+        // the loud half of every envelope period
+        let delays = spectra(generator, 200)
+            .iter()
+            .filter_map(|[left, right]| estimator.estimate_from_spectra(left, right, None).ok())
+            .filter(|x| x.confidence > 0.5)
+            .map(|x| x.delay)
+            .collect();
+
+        let delay = median(delays);
+        assert!((delay - 200e-6).abs() < 10e-6, "{delay}");
+    }
+
+    #[test]
+    fn cfar_gating_recovers_speech_and_skips_degenerate_masks() {
+        let generator = SignalGenerator::new(RATE, 1024, SourceKind::SpeechNoise, 200e-6)
+            .unwrap()
+            .with_noise(20.0);
+        let mut processor = SignalProcessor::new(RATE);
+        let mut estimator = GccEstimator::new(RATE);
+        estimator.set_band(Some((300.0, 4000.0)));
+        estimator.set_max_lag(Some(max_lag(0.2, 0.1)));
+        estimator.set_min_bins(8);
+
+        let mut delays = Vec::new();
+        for [left, right] in spectra(generator, 200) {
+            let plot = processor.complex_fft_to_db_magnitude(&left);
+            let magnitude: Vec<f32> = plot.iter().map(|(_, y)| *y).collect();
+            let threshold =
+                processor.add_frequency_resolution(SignalProcessor::cfar(&magnitude, 10, 4, 3.5));
+            let detected = cfar_detections(&plot, &threshold).unwrap();
+
+            if let Ok(estimate) = estimator.estimate_from_spectra(&left, &right, Some(&detected)) {
+                // the bins the mask drops count against the confidence
+                assert!(estimate.confidence < 0.5, "{}", estimate.confidence);
+                delays.push(estimate.delay);
+            }
+
+            // two detected bins are a cosine, not an estimate
+            let mut sparse = vec![false; left.len()];
+            sparse[20] = true;
+            sparse[40] = true;
+            assert!(
+                estimator
+                    .estimate_from_spectra(&left, &right, Some(&sparse))
+                    .is_err()
+            );
+        }
+
+        let delay = median(delays);
+        assert!((delay - 200e-6).abs() < 10e-6, "{delay}");
+    }
+}
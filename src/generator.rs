@@ -0,0 +1,534 @@
+use crate::SPEED_SOUND;
+use crate::audio::AudioSource;
+use crate::error::{Error, Result};
+use rustfft::num_complex::Complex32;
+use std::f32::consts::PI;
+
+/// Half width, in samples, of the windowed sinc used for fractional delays.
+const SINC_HALF_WIDTH: usize = 16;
+
+pub enum SourceKind {
+    Tone {
+        frequency: f32,
+    },
+    /// Linear sweep from `start_frequency` to `end_frequency`, repeated every `period` seconds.
+    Chirp {
+        start_frequency: f32,
+        end_frequency: f32,
+        period: f32,
+    },
+    WhiteNoise,
+    /// Noise band-limited to 300-3400 Hz with a 4 Hz syllable-like envelope.
+    SpeechNoise,
+}
+
+/// Shoebox room used to add image-source reflections on top of the direct path.
+///
+/// Only the extra path length of every image relative to the direct path is used, so the
+/// inter-mic delay of the direct path stays the one given to [`SignalGenerator::new`].
+pub struct RoomReverb {
+    /// Room dimensions in metres, the room spans `0..room_size` on every axis.
+    pub room_size: [f32; 3],
+    pub source: [f32; 3],
+    /// Left and right microphone positions.
+    pub microphones: [[f32; 3]; 2],
+    /// Wall reflection coefficient, `0.0` is anechoic and `1.0` a perfect mirror.
+    pub reflection: f32,
+    /// Maximum number of wall bounces per image.
+    pub order: usize,
+}
+
+/// Synthetic stereo input with a known inter-mic delay.
+///
+/// The left channel hears the source `delay` seconds later than the right one, which is the
+/// sign the GCC-PHAT peak reports, so a positive delay gives a positive peak time. The delay
+/// does not need to be a whole number of samples.
+pub struct SignalGenerator {
+    samples_per_sec: u32,
+    block_size: usize,
+    source: SourceKind,
+    // standard deviation of the noise added to every channel
+    noise_std: Option<f32>,
+    // (delay in samples, gain) of every path arriving at the left and right microphone
+    taps: [Vec<(f32, f32)>; 2],
+    history: Vec<f32>,
+    // absolute index of history[0], older samples are dropped once no tap can reach them
+    history_start: usize,
+    position: usize,
+    chirp_phase: f32,
+    band_pass: Biquad,
+    rng: Rng,
+}
+
+impl SignalGenerator {
+    pub fn new(
+        samples_per_sec: u32,
+        block_size: usize,
+        source: SourceKind,
+        delay: f32,
+    ) -> Result<Self> {
+        if block_size == 0 {
+            return Err(Error::Config(String::from("Block size must be non zero")));
+        }
+
+        let fs = samples_per_sec as f32;
+
+        // every path is delayed by at least the sinc half width so the interpolation never
+        // needs samples that were not generated yet
+        let latency = SINC_HALF_WIDTH as f32 + 1.0;
+        let left = latency + (delay * fs).max(0.0);
+        let right = latency + (-delay * fs).max(0.0);
+
+        Ok(SignalGenerator {
+            samples_per_sec,
+            block_size,
+            source,
+            noise_std: None,
+            taps: [vec![(left, 1.0)], vec![(right, 1.0)]],
+            history: Vec::new(),
+            history_start: 0,
+            position: 0,
+            chirp_phase: 0.0,
+            band_pass: Biquad::band_pass(fs, 300.0, 3400.0),
+            rng: Rng::new(0x5EED),
+        })
+    }
+
+    /// Adds uncorrelated white noise to each channel at `snr_db` below the mean power of the
+    /// source. The level is fixed, so it stays the same through quiet stretches of the source.
+    pub fn with_noise(mut self, snr_db: f32) -> Self {
+        let power = self.source_power() / 10f32.powf(snr_db / 10.0);
+        self.noise_std = Some(power.sqrt());
+        self
+    }
+
+    /// Adds the image-source reflections of `room` to both channels.
+    pub fn with_reverb(mut self, room: RoomReverb) -> Self {
+        let fs = self.samples_per_sec as f32;
+
+        for (channel, mic) in room.microphones.iter().enumerate() {
+            let (direct_delay, _) = self.taps[channel][0];
+            let direct = distance(mic, &room.source);
+
+            for (image, bounces) in image_sources(&room) {
+                if bounces == 0 {
+                    continue;
+                }
+
+                let path = distance(mic, &image);
+                let extra = (path - direct) / SPEED_SOUND as f32 * fs;
+                let gain = room.reflection.powi(bounces as i32) * direct / path;
+
+                self.taps[channel].push((direct_delay + extra, gain));
+            }
+        }
+
+        self
+    }
+
+    /// Seeds the noise generator, every seed gives a reproducible sequence.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// Mean power of the source signal over a long run.
+    fn source_power(&self) -> f32 {
+        match self.source {
+            SourceKind::Tone { .. } | SourceKind::Chirp { .. } => 0.5,
+            SourceKind::WhiteNoise => 1.0,
+            // the half-wave rectified sine envelope has a mean square of 1/4
+            SourceKind::SpeechNoise => {
+                let fs = self.samples_per_sec as f32;
+                Biquad::band_pass(fs, 300.0, 3400.0).noise_gain(self.samples_per_sec as usize) / 4.0
+            }
+        }
+    }
+
+    fn next_source_sample(&mut self) -> f32 {
+        let fs = self.samples_per_sec as f32;
+        let n = self.history_start + self.history.len();
+        let t = n as f32 / fs;
+
+        match self.source {
+            SourceKind::Tone { frequency } => {
+                // keep the phase in f64 so long runs don't drift
+                let cycles = frequency as f64 * n as f64 / self.samples_per_sec as f64;
+                (2.0 * PI * cycles.fract() as f32).sin()
+            }
+            SourceKind::Chirp {
+                start_frequency,
+                end_frequency,
+                period,
+            } => {
+                let progress =
+                    (n as f64 / self.samples_per_sec as f64 % period as f64) as f32 / period;
+                let frequency = start_frequency + (end_frequency - start_frequency) * progress;
+                self.chirp_phase = (self.chirp_phase + 2.0 * PI * frequency / fs) % (2.0 * PI);
+                self.chirp_phase.sin()
+            }
+            SourceKind::WhiteNoise => self.rng.gaussian(),
+            SourceKind::SpeechNoise => {
+                let noise = self.rng.gaussian();
+                let envelope = (2.0 * PI * 4.0 * t).sin().max(0.0);
+                self.band_pass.process(noise) * envelope
+            }
+        }
+    }
+
+    fn delayed(&self, n: usize, delay: f32) -> f32 {
+        // windowed sinc interpolation of the source at (n - delay), the integer part is kept
+        // apart so the fraction doesn't lose precision once n gets large
+        let whole = delay.floor();
+        let fraction = delay - whole;
+        let base = n as i64 - whole as i64;
+
+        let width = SINC_HALF_WIDTH as i64;
+        let start = self.history_start as i64;
+        let end = start + self.history.len() as i64;
+
+        (base - width..base + width)
+            .filter(|k| *k >= start && *k < end)
+            .map(|k| {
+                let x = (base - k) as f32 - fraction;
+                let window = 0.5 * (1.0 + (PI * x / (width as f32 + 1.0)).cos());
+                self.history[(k - start) as usize] * sinc(x) * window
+            })
+            .sum()
+    }
+}
+
+impl AudioSource for SignalGenerator {
+    fn sample_rate(&self) -> u32 {
+        self.samples_per_sec
+    }
+
+    fn channel_count(&self) -> usize {
+        2
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn next_block(&mut self) -> Option<Vec<Vec<Complex32>>> {
+        let start = self.position;
+        self.position += self.block_size;
+
+        while self.history_start + self.history.len() < self.position {
+            let sample = self.next_source_sample();
+            self.history.push(sample);
+        }
+
+        let mut channels: Vec<Vec<f32>> = self
+            .taps
+            .iter()
+            .map(|taps| {
+                (start..self.position)
+                    .map(|n| {
+                        taps.iter()
+                            .map(|(delay, gain)| gain * self.delayed(n, *delay))
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // the next block only reaches back by the longest tap plus the sinc width
+        let longest = self
+            .taps
+            .iter()
+            .flatten()
+            .map(|(delay, _)| delay.ceil() as usize)
+            .max()
+            .unwrap_or(0);
+        let keep_from = self.position.saturating_sub(longest + 2 * SINC_HALF_WIDTH);
+        if keep_from > self.history_start {
+            self.history.drain(..keep_from - self.history_start);
+            self.history_start = keep_from;
+        }
+
+        if let Some(noise_std) = self.noise_std {
+            for channel in channels.iter_mut() {
+                for x in channel.iter_mut() {
+                    *x += noise_std * self.rng.gaussian();
+                }
+            }
+        }
+
+        Some(
+            channels
+                .into_iter()
+                .map(|channel| channel.into_iter().map(Complex32::from).collect())
+                .collect(),
+        )
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// All image sources of the shoebox room with at most `room.order` bounces, with their bounce count.
+fn image_sources(room: &RoomReverb) -> Vec<([f32; 3], usize)> {
+    let order = room.order as i32;
+
+    // per axis: (image coordinate, bounces) for every mirror index
+    let axis_images: Vec<Vec<(f32, usize)>> = (0..3)
+        .map(|axis| {
+            let size = room.room_size[axis];
+            let source = room.source[axis];
+
+            (-order..=order)
+                .flat_map(|n| {
+                    let offset = 2.0 * n as f32 * size;
+                    [
+                        (offset + source, (2 * n).unsigned_abs() as usize),
+                        (offset - source, (2 * n - 1).unsigned_abs() as usize),
+                    ]
+                })
+                .filter(|(_, bounces)| *bounces <= room.order)
+                .collect()
+        })
+        .collect();
+
+    let mut images = Vec::new();
+
+    for (x, bx) in &axis_images[0] {
+        for (y, by) in &axis_images[1] {
+            for (z, bz) in &axis_images[2] {
+                let bounces = bx + by + bz;
+                if bounces <= room.order {
+                    images.push(([*x, *y, *z], bounces));
+                }
+            }
+        }
+    }
+
+    images
+}
+
+/// Second order band-pass section (RBJ cookbook, constant peak gain).
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    fn band_pass(samples_per_sec: f32, low: f32, high: f32) -> Self {
+        let center = (low * high).sqrt();
+        let q = center / (high - low);
+        let w0 = 2.0 * PI * center / samples_per_sec;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        Biquad {
+            b: [alpha / a0, 0.0, -alpha / a0],
+            a: [-2.0 * w0.cos() / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// Output power for white noise of unit variance, the energy of the first `len` samples
+    /// of the impulse response.
+    fn noise_gain(mut self, len: usize) -> f32 {
+        (0..len)
+            .map(|n| self.process(if n == 0 { 1.0 } else { 0.0 }).powi(2))
+            .sum()
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+
+        output
+    }
+}
+
+/// Small xorshift generator so the crate does not need a dependency for test noise.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng { state: seed.max(1) }
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn gaussian(&mut self) -> f32 {
+        // Box-Muller
+        let u1 = self.next_f32().max(f32::MIN_POSITIVE);
+        let u2 = self.next_f32();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn power(block: &[Complex32]) -> f32 {
+        block.iter().map(|x| x.re * x.re).sum::<f32>() / block.len() as f32
+    }
+
+    #[test]
+    fn noise_level_follows_the_source_power() {
+        let mut generator = SignalGenerator::new(48000, 4800, SourceKind::SpeechNoise, 0.0)
+            .unwrap()
+            .with_noise(10.0);
+        let expected = generator.source_power();
+
+        // ten seconds, forty periods of the envelope
+        let total: f32 = (0..100)
+            .map(|_| power(&generator.next_block().unwrap()[0]))
+            .sum::<f32>()
+            / 100.0;
+
+        assert!(
+            (total / (expected * 1.1) - 1.0).abs() < 0.1,
+            "{total} vs {expected}"
+        );
+    }
+
+    #[test]
+    fn rejects_empty_blocks() {
+        assert!(matches!(
+            SignalGenerator::new(48000, 0, SourceKind::WhiteNoise, 0.0),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn silence_of_speech_noise_keeps_its_noise() {
+        // the envelope is zero for the second half of every 250 ms period
+        let mut generator = SignalGenerator::new(48000, 1200, SourceKind::SpeechNoise, 0.0)
+            .unwrap()
+            .with_noise(20.0);
+        let expected = generator.source_power() / 100.0;
+
+        let blocks: Vec<Vec<Vec<Complex32>>> =
+            (0..10).map(|_| generator.next_block().unwrap()).collect();
+
+        // 150 to 225 ms, clear of the sinc latency at the edges of the quiet stretch
+        for block in &blocks[6..9] {
+            for channel in block {
+                let noise = power(channel);
+                assert!(
+                    (noise / expected - 1.0).abs() < 0.3,
+                    "{noise} vs {expected}"
+                );
+            }
+        }
+    }
+
+    fn room(reflection: f32, order: usize) -> RoomReverb {
+        RoomReverb {
+            room_size: [5.0, 4.0, 3.0],
+            source: [1.0, 2.5, 1.5],
+            microphones: [[3.9, 1.5, 1.2], [4.1, 1.5, 1.2]],
+            reflection,
+            order,
+        }
+    }
+
+    #[test]
+    fn first_order_images_mirror_the_source_in_every_wall() {
+        let images = image_sources(&room(0.5, 1));
+        assert_eq!(images.len(), 7);
+
+        let direct: Vec<_> = images.iter().filter(|(_, b)| *b == 0).collect();
+        assert_eq!(direct.len(), 1);
+        assert_eq!(direct[0].0, [1.0, 2.5, 1.5]);
+
+        for expected in [
+            [-1.0, 2.5, 1.5],
+            [9.0, 2.5, 1.5],
+            [1.0, -2.5, 1.5],
+            [1.0, 5.5, 1.5],
+            [1.0, 2.5, -1.5],
+            [1.0, 2.5, 4.5],
+        ] {
+            assert!(
+                images
+                    .iter()
+                    .any(|(image, b)| *b == 1 && *image == expected),
+                "{expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reflections_arrive_after_and_weaker_than_the_direct_path() {
+        let generator = SignalGenerator::new(48000, 1024, SourceKind::WhiteNoise, 250e-6)
+            .unwrap()
+            .with_reverb(room(0.7, 2));
+
+        for taps in &generator.taps {
+            let (direct_delay, direct_gain) = taps[0];
+            assert_eq!(direct_gain, 1.0);
+            // the direct path and the 24 images with one or two bounces
+            assert_eq!(taps.len(), 25);
+            for (delay, gain) in &taps[1..] {
+                assert!(*delay > direct_delay, "{delay} vs {direct_delay}");
+                assert!(*gain > 0.0 && *gain < 0.7, "{gain}");
+            }
+        }
+    }
+
+    #[test]
+    fn anechoic_room_leaves_the_signal_unchanged() {
+        let mut dry = SignalGenerator::new(48000, 1024, SourceKind::WhiteNoise, 250e-6).unwrap();
+        let mut wet = SignalGenerator::new(48000, 1024, SourceKind::WhiteNoise, 250e-6)
+            .unwrap()
+            .with_reverb(room(0.0, 1));
+
+        for _ in 0..3 {
+            assert_eq!(dry.next_block(), wet.next_block());
+        }
+    }
+
+    #[test]
+    fn reverb_keeps_the_direct_path_delay() {
+        let mut generator = SignalGenerator::new(48000, 4096, SourceKind::WhiteNoise, 250e-6)
+            .unwrap()
+            .with_reverb(room(0.6, 2));
+        let mut estimator = crate::gcc::GccEstimator::new(48000);
+
+        generator.next_block();
+        for _ in 0..4 {
+            let block = generator.next_block().unwrap();
+            let estimate = estimator.estimate(&block[0], &block[1]).unwrap();
+            assert!(
+                (estimate.delay - 250e-6).abs() < 1.0 / 48000.0,
+                "{}",
+                estimate.delay
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::SPEED_SOUND;
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::fs;
+
+/// Position of one microphone and the input channel it is wired to.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Microphone {
+    pub channel: usize,
+    /// Coordinates in metres, `z` is `0.0` for planar arrays.
+    pub position: [f64; 3],
+}
+
+/// Microphone layout of the array, replacing the single `mic_dis` baseline.
+///
+/// The geometry file has one microphone per line as `channel,x,y` or `channel,x,y,z` in
+/// metres, empty lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayGeometry {
+    microphones: Vec<Microphone>,
+}
+
+impl ArrayGeometry {
+    /// Validates the layout: at least two microphones, unique channels, finite coordinates
+    /// and no two microphones closer than a millimetre.
+    pub fn new(microphones: Vec<Microphone>) -> Result<Self> {
+        if microphones.len() < 2 {
+            return Err(Error::Config(format!(
+                "Array needs at least 2 microphones, found {}",
+                microphones.len()
+            )));
+        }
+
+        for (i, mic) in microphones.iter().enumerate() {
+            if mic.position.iter().any(|x| !x.is_finite()) {
+                return Err(Error::Config(format!(
+                    "Microphone on channel {} has a non finite position",
+                    mic.channel
+                )));
+            }
+
+            for other in &microphones[..i] {
+                if other.channel == mic.channel {
+                    return Err(Error::Config(format!(
+                        "Channel {} is used by two microphones",
+                        mic.channel
+                    )));
+                }
+
+                if distance(&other.position, &mic.position) < 1e-3 {
+                    return Err(Error::Config(format!(
+                        "Microphones on channel {} and {} are at the same position",
+                        other.channel, mic.channel
+                    )));
+                }
+            }
+        }
+
+        Ok(ArrayGeometry { microphones })
+    }
+
+    /// Two microphone bar along x, channel 0 (left) at `-mic_dis / 2` and channel 1 (right)
+    /// at `+mic_dis / 2`.
+    pub fn two_mic_bar(mic_dis: f64) -> Result<Self> {
+        ArrayGeometry::new(vec![
+            Microphone {
+                channel: 0,
+                position: [-mic_dis / 2.0, 0.0, 0.0],
+            },
+            Microphone {
+                channel: 1,
+                position: [mic_dis / 2.0, 0.0, 0.0],
+            },
+        ])
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Self::parse(&contents).map_err(|e| match e {
+            Error::Config(message) => Error::Config(format!("{path}: {message}")),
+            e => e,
+        })
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut microphones = Vec::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+            if fields.len() != 3 && fields.len() != 4 {
+                return Err(Error::Config(format!(
+                    "line {}: expected channel,x,y[,z] but found {} values",
+                    line_number + 1,
+                    fields.len()
+                )));
+            }
+
+            let channel = fields[0].parse::<usize>().map_err(|_| {
+                Error::Config(format!(
+                    "line {}: couldn't parse channel {:?}",
+                    line_number + 1,
+                    fields[0]
+                ))
+            })?;
+
+            let mut position = [0.0; 3];
+            for (axis, field) in fields[1..].iter().enumerate() {
+                position[axis] = field.parse::<f64>().map_err(|_| {
+                    Error::Config(format!(
+                        "line {}: couldn't parse {:?} as a float",
+                        line_number + 1,
+                        field
+                    ))
+                })?;
+            }
+
+            microphones.push(Microphone { channel, position });
+        }
+
+        ArrayGeometry::new(microphones)
+    }
+
+    pub fn microphones(&self) -> &[Microphone] {
+        &self.microphones
+    }
+
+    pub fn microphone(&self, channel: usize) -> Option<&Microphone> {
+        self.microphones.iter().find(|x| x.channel == channel)
+    }
+
+    /// Highest channel index plus one, the input needs at least this many channels.
+    pub fn required_channels(&self) -> usize {
+        self.microphones
+            .iter()
+            .map(|x| x.channel + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The first two microphones of the layout, ordered by channel. The angle plot and the
+    /// TCP output use this pair like the left/right pair of the two microphone bar.
+    pub fn reference_pair(&self) -> (usize, usize) {
+        let (a, b) = (self.microphones[0].channel, self.microphones[1].channel);
+        (a.min(b), a.max(b))
+    }
+
+    /// True if every microphone has the same `z`, such arrays only resolve azimuth reliably.
+    pub fn is_planar(&self) -> bool {
+        let z = self.microphones[0].position[2];
+        self.microphones
+            .iter()
+            .all(|x| (x.position[2] - z).abs() < 1e-6)
+    }
+
+    /// Spacing of two channels in metres.
+    pub fn distance(&self, a: usize, b: usize) -> Option<f64> {
+        Some(distance(
+            &self.microphone(a)?.position,
+            &self.microphone(b)?.position,
+        ))
+    }
+
+    /// Largest delay the pair can observe, in seconds.
+    pub fn max_delay(&self, a: usize, b: usize) -> Option<f64> {
+        Some(self.distance(a, b)? / SPEED_SOUND)
+    }
+
+    /// Angle from the broadside of the pair, positive towards microphone `b`, for a delay
+    /// of channel `a` after channel `b` in seconds.
+    pub fn delay_to_angle(&self, a: usize, b: usize, delay: f32) -> Option<f32> {
+        let spacing = self.distance(a, b)? as f32;
+        Some(
+            (delay * SPEED_SOUND as f32 / spacing)
+                .clamp(-1.0, 1.0)
+                .asin(),
+        )
+    }
+
+    /// Delay of channel `a` after channel `b` for a source at `angle` from the broadside of
+    /// the pair, the inverse of [`Self::delay_to_angle`].
+    pub fn angle_to_delay(&self, a: usize, b: usize, angle: f32) -> Option<f32> {
+        let spacing = self.distance(a, b)? as f32;
+        Some(angle.sin() * spacing / SPEED_SOUND as f32)
+    }
+
+    /// Smallest angle step at broadside a delay resolution of `time_resolution` gives.
+    pub fn angle_resolution(&self, a: usize, b: usize, time_resolution: f32) -> Option<f32> {
+        self.delay_to_angle(a, b, time_resolution)
+    }
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(contents: &str) -> String {
+        match ArrayGeometry::parse(contents) {
+            Err(Error::Config(message)) => message,
+            other => panic!("expected a configuration error, found {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_planar_and_3d_layouts() {
+        let geometry =
+            ArrayGeometry::parse("# channel,x,y,z\n\n2, 0.1, 0.0\n 0,-0.1,0.0 \n3,0.0,0.1,0.05\n")
+                .unwrap();
+
+        assert_eq!(geometry.microphones().len(), 3);
+        assert_eq!(geometry.reference_pair(), (0, 2));
+        assert_eq!(geometry.required_channels(), 4);
+        assert!(!geometry.is_planar());
+        assert!((geometry.distance(0, 2).unwrap() - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for (contents, expected) in [
+            (
+                "0,0.0\n1,0.1,0.0",
+                "line 1: expected channel,x,y[,z] but found 2 values",
+            ),
+            ("0,0.0,0.0\n1,0.1,0.0,0.0,0.0", "line 2: expected"),
+            ("a,0.0,0.0\n1,0.1,0.0", "line 1: couldn't parse channel"),
+            ("-1,0.0,0.0\n1,0.1,0.0", "line 1: couldn't parse channel"),
+            ("0,0.0,0.0\n1,0.1cm,0.0", "line 2: couldn't parse \"0.1cm\""),
+            ("0,0.0,0.0\n\n1,,0.0", "line 3: couldn't parse \"\""),
+        ] {
+            let message = message(contents);
+            assert!(message.contains(expected), "{contents:?}: {message}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_layouts() {
+        assert!(message("").contains("at least 2 microphones"));
+        assert!(message("0,0.0,0.0").contains("at least 2 microphones"));
+        assert!(message("0,0.0,0.0\n0,0.1,0.0").contains("used by two microphones"));
+        assert!(message("0,0.0,0.0\n1,0.0,0.0").contains("same position"));
+        assert!(message("0,0.0,0.0\n1,nan,0.0").contains("non finite"));
+        assert!(message("0,0.0,0.0\n1,inf,0.0").contains("non finite"));
+    }
+
+    #[test]
+    fn delay_and_angle_are_inverse() {
+        let geometry = ArrayGeometry::two_mic_bar(0.2).unwrap();
+        for angle in [-1.2f32, -0.3, 0.0, 0.4, 1.1] {
+            let delay = geometry.angle_to_delay(0, 1, angle).unwrap();
+            let back = geometry.delay_to_angle(0, 1, delay).unwrap();
+            assert!((back - angle).abs() < 1e-4, "{angle} came back as {back}");
+        }
+    }
+}
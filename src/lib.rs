@@ -1,78 +1,33 @@
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::thread;
-use std::time::Duration;
-
-pub struct TcpClient {
-    route: String,
-    stream: Option<TcpStream>,
-    pub h: f64,
-    pub k: f64,
-    pub phi: f64,
-    pub mic_dis: f64,
-    pub del_t: f64,
-    pub timestamp: u64,
-}
-
-impl TcpClient {
-    pub fn new(route: String, h: f64, k: f64, phi: f64, mic_dis: f64) -> Self {
-        let mut client = TcpClient {
-            route,
-            stream: None,
-            h,
-            k,
-            phi,
-            mic_dis,
-            del_t: 0.0,
-            timestamp: 0,
-        };
-
-        client.connect();
-        client
-    }
-
-    fn connect(&mut self) {
-        loop {
-            match TcpStream::connect(&self.route) {
-                Ok(stream) => {
-                    let _ = stream.set_nodelay(true);
-                    let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
-                    println!("Connected to {}", self.route);
-                    self.stream = Some(stream);
-                    break;
-                }
-                Err(e) => {
-                    eprintln!("Connect failed: {e}, retrying...");
-                    thread::sleep(Duration::from_secs(1));
-                }
-            }
-        }
-    }
-
-    pub fn send(&mut self) {
-        let data = format!(
-            "{},{},{},{},{},{}\n",
-            self.timestamp, self.h, self.k, self.phi, self.mic_dis, self.del_t
-        );
-
-        if let Some(stream) = self.stream.as_mut() {
-            if let Err(e) = stream
-                .write_all(data.as_bytes())
-                .and_then(|_| stream.flush())
-            {
-                eprintln!("Send error: {e}");
-                self.stream = None; // drop broken stream
-                self.connect(); // reconnect
-            }
-        } else {
-            self.connect();
-        }
-    }
-}
+use error::{Error, Result};
+use gcc::PeakQuality;
+
+pub mod audio;
+pub mod config;
+pub mod error;
+pub mod frame;
+pub mod gcc;
+pub mod generator;
+pub mod geometry;
+pub mod music;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod pipeline;
+pub mod signal;
+pub mod srp;
+pub mod tracking;
+pub mod vad;
+
+#[cfg(feature = "net")]
+pub use net::TcpClient;
+
+pub const SPEED_SOUND: f64 = 343.0;
+
+/// `(track id, delay, confidence, peak quality)` of every talker in a frame.
+pub type TrackReport = Vec<(u32, f64, f32, PeakQuality)>;
 
 pub fn find_peak_index(
     min_max_range: (f32, f32),
-    fft_db_array: &Vec<(f32, f32)>,
+    fft_db_array: &[(f32, f32)],
     angular_resolution: f32,
 ) -> Option<usize> {
     let (min, max) = min_max_range;
@@ -98,18 +53,27 @@ pub fn find_peak_index(
     Some(max_index)
 }
 
+/// Keeps the bins of `fft_db_array` above their CFAR threshold and zeroes the others. Fails
+/// if the threshold isn't computed for the same frequencies.
 pub fn filter_with_cfar(
-    fft_db_array: &Vec<(f32, f32)>,
-    cfar_db_array: &Vec<(f32, f32)>,
-) -> Vec<(f32, f32)> {
+    fft_db_array: &[(f32, f32)],
+    cfar_db_array: &[(f32, f32)],
+) -> Result<Vec<(f32, f32)>> {
+    if fft_db_array.len() != cfar_db_array.len() {
+        return Err(Error::Dsp(
+            "CFAR threshold has a different length than the spectrum",
+        ));
+    }
+
     fft_db_array
         .iter()
         .zip(cfar_db_array)
         .map(|((x1, y1), (x2, y2))| {
-            assert!(
-                (x1 - x2).abs() < 0.01,
-                "CFAR filtering Frequency Assertion Failled"
-            );
+            if (x1 - x2).abs() >= 0.01 {
+                return Err(Error::Dsp(
+                    "CFAR threshold frequencies don't match the spectrum",
+                ));
+            }
 
             let value = if y1 > y2 {
                 // cfar value low
@@ -118,11 +82,22 @@ pub fn filter_with_cfar(
                 0.0
             };
 
-            (*x1, value)
+            Ok((*x1, value))
         })
         .collect()
 }
 
+/// Bins that rise above their CFAR threshold, see [`filter_with_cfar`].
+pub fn cfar_detections(
+    fft_db_array: &[(f32, f32)],
+    cfar_db_array: &[(f32, f32)],
+) -> Result<Vec<bool>> {
+    Ok(filter_with_cfar(fft_db_array, cfar_db_array)?
+        .iter()
+        .map(|(_, y)| *y != 0.0)
+        .collect())
+}
+
 pub fn angle_wrap_f32(angle: f32) -> f32 {
     use std::f32::consts::PI;
 
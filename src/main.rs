@@ -1,175 +1,340 @@
-use audio::StreamEncapsulate;
-use butterworth::Cutoff;
-use butterworth::Filter;
-use cpal::traits::StreamTrait;
+use clap::{Args, Parser, Subcommand};
+#[cfg(feature = "gui")]
 use eframe::NativeOptions;
-use signal::SignalProcessor;
+#[cfg(feature = "gui")]
 use std::collections::VecDeque;
-use std::fs::File;
-use std::io::Read;
-use std::sync::mpsc;
+use std::path::Path;
+use std::process::ExitCode;
+#[cfg(feature = "gui")]
+use std::sync::mpsc::{self, SyncSender};
+#[cfg(feature = "gui")]
 use std::thread;
-use std::time::SystemTime;
-use ui::Application;
+use std::time::Duration;
+#[cfg(feature = "gui")]
+use ui::{Application, DevicePicker, PlotReceivers};
 use voice_direction_finder::TcpClient;
-
-use rustfft::num_complex::Complex32;
-
-mod audio;
-mod signal;
+#[cfg(feature = "gui")]
+use voice_direction_finder::TrackReport;
+#[cfg(feature = "gui")]
+use voice_direction_finder::audio::CaptureConfig;
+use voice_direction_finder::audio::{
+    self, AudioSource, DeviceSelector, FileStreamEncapsulate, StreamEncapsulate, list_input_devices,
+};
+use voice_direction_finder::config::{CaptureSettings, Config};
+use voice_direction_finder::error::{Error, Result};
+#[cfg(feature = "gui")]
+use voice_direction_finder::gcc::{PeakQuality, Weighting};
+#[cfg(feature = "gui")]
+use voice_direction_finder::music::MusicSpectrum;
+use voice_direction_finder::pipeline::Pipeline;
+#[cfg(feature = "gui")]
+use voice_direction_finder::srp::SrpMap;
+
+mod calibrate;
+#[cfg(feature = "gui")]
 mod ui;
 
-const DEVICE: &str = "default";
-const SPEED_SOUND: f64 = 343.0;
-
-fn main() -> Result<(), eframe::Error> {
-    let mut file = File::open("params.csv").unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    contents = contents.trim().to_string();
-    let parameter_vals = contents
-        .split(',')
-        .map(|val| {
-            println!("val: {}", val);
-            val.parse::<f64>().expect("Couldn't parse {val} as a float")
-        })
-        .collect::<Vec<f64>>();
-
-    let h = *parameter_vals.get(0).expect("Value of h doesn't exist");
-    let k = *parameter_vals.get(1).expect("Value of k doesn't exist");
-    let phi = *parameter_vals.get(2).expect("Value of phi doesn't exist");
-    let mic_dis = *parameter_vals
-        .get(3)
-        .expect("Value of mic_dis doesn't exist");
-
-    let stream_encapsulate = StreamEncapsulate::new(DEVICE); // Spawned New Thread Here
-    stream_encapsulate.stream.play().unwrap(); // Runs the thread
-
-    let mut signal_processor = SignalProcessor::new(stream_encapsulate.samples_per_sec);
-
-    println!(
-        "The time resolution is: {}",
-        signal_processor.get_time_resolution()
-    );
-
-    let angle_resolution =
-        (signal_processor.get_time_resolution() * (SPEED_SOUND / mic_dis) as f32).asin();
-    println!(
-        "angle_resolution: {} degrees",
-        angle_resolution * 180.0 / std::f32::consts::PI
-    );
-
-    let mut phase_queue: VecDeque<f32> = VecDeque::new();
-
-    let filter = Filter::new(2, 20000.0, Cutoff::LowPass(6000.0)).unwrap();
+// read unless --config names another file
+const CONFIG_FILE: &str = "voice_direction_finder.toml";
+// the positional h, k, phi and mic_dis file the TOML config replaced
+const LEGACY_PARAMS: &str = "params.csv";
+
+/// Finds the direction of talkers from a microphone array and sends it to a server.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-    let (app_right_tx, app_right_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
-    let (app_left_tx, app_left_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
-    let (app_left_cfar_tx, app_left_cfar_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
-    let (app_right_cfar_tx, app_right_cfar_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
-    let (cross_correlation_tx, cross_correlation_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
-    let (phase_tx, phase_rx) = mpsc::sync_channel::<VecDeque<f32>>(1);
-    let (socket_tx, socket_rx) = mpsc::sync_channel::<f64>(1);
+#[derive(Subcommand)]
+enum Command {
+    /// Process the live input and show the plots.
+    Run(Options),
+    /// Process the live input without a window.
+    Headless(Options),
+    /// List the input devices and the configurations they support.
+    ListDevices,
+    /// Capture the live input to a WAV file that `replay` can process.
+    Record {
+        #[command(flatten)]
+        options: Options,
+        output: String,
+        /// Stop after this many seconds instead of recording until killed.
+        #[arg(long)]
+        seconds: Option<f64>,
+    },
+    /// Process a WAV recording instead of the live input.
+    Replay {
+        #[command(flatten)]
+        options: Options,
+        input: String,
+        #[arg(long)]
+        headless: bool,
+        /// Send the talkers to the network server like the live input does.
+        #[arg(long)]
+        send: bool,
+    },
+    /// Measure the reference pair with a source at a known angle and suggest the spacing.
+    Calibrate {
+        #[command(flatten)]
+        options: Options,
+        /// Degrees from the broadside of the pair, positive towards its second microphone.
+        #[arg(long, default_value_t = 90.0, allow_negative_numbers = true)]
+        angle: f32,
+        #[arg(long, default_value_t = 10.0)]
+        seconds: f64,
+        /// Calibrate from a WAV recording instead of the live input.
+        #[arg(long)]
+        input: Option<String>,
+    },
+}
 
-    // let mut prev_time = SystemTime::now()
-    //     .duration_since(SystemTime::UNIX_EPOCH)
-    //     .unwrap();
+/// Settings every processing command takes.
+#[derive(Args)]
+struct Options {
+    /// TOML config, defaults to voice_direction_finder.toml.
+    #[arg(short, long)]
+    config: Option<String>,
+    /// Input device as "default", an index from `list-devices`, a name or `*part*` of one.
+    #[arg(short, long)]
+    device: Option<String>,
+    /// Overrides a config key, e.g. `--set dsp.weighting=scot`, repeatable.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+}
 
-    thread::spawn(move || {
-        let mut client = TcpClient::new(String::from("10.84.222.62:9099"), h, k, phi, mic_dis);
-        loop {
-            if let Ok(val) = socket_rx.recv() {
-                client.del_t = val;
-                println!("{val}");
-                client.timestamp = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64;
-                client.send();
-            }
+impl Options {
+    fn config(&self) -> Result<Config> {
+        // a node still set up the old way would otherwise run with a pose it never had
+        if Path::new(LEGACY_PARAMS).exists() {
+            return Err(Error::Config(format!(
+                "{LEGACY_PARAMS} is no longer read, move its h, k and phi to [node] and \
+                 mic_dis to array.mic_distance of {CONFIG_FILE}, see \
+                 voice_direction_finder.example.toml, then delete it"
+            )));
         }
-    });
 
-    thread::spawn(move || {
-        // Signal Processing Thread
-        loop {
-            //println!("LOOPING FFT LOOP");
-
-            if let Ok(mut left_data) = stream_encapsulate.left_rx.recv()
-                && let Ok(mut right_data) = stream_encapsulate.right_rx.recv()
-            {
-                let right_fft = signal_processor.fft(&mut right_data);
-                let right_magnitude_plot = signal_processor.complex_fft_to_db_magnitude(&right_fft);
+        let path = self.config.as_deref().unwrap_or(CONFIG_FILE);
+        if !Path::new(path).exists() {
+            return Err(Error::Config(format!(
+                "{path} not found, copy voice_direction_finder.example.toml and set the node \
+                 pose or pass another file with --config"
+            )));
+        }
 
-                let left_fft = signal_processor.fft(&mut left_data);
-                let left_magnitude_plot = signal_processor.complex_fft_to_db_magnitude(&left_fft);
+        let mut config = Config::load_with_overrides(Some(path), &self.overrides)?;
+        if let Some(device) = &self.device {
+            config.capture.device = device.clone();
+        }
+        Ok(config)
+    }
+}
 
-                // cfar left
-                let var: Vec<f32> = left_magnitude_plot.iter().map(|(_x, y)| *y).collect();
-                let cfar_left = SignalProcessor::cfar(&var, 10, 4, 3.5);
-                let cfar_left = signal_processor.add_frequency_resolution(cfar_left);
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-                // cfar right
-                let var: Vec<f32> = right_magnitude_plot.iter().map(|(_x, y)| *y).collect();
-                let cfar_right = SignalProcessor::cfar(&var, 10, 4, 3.5);
-                let cfar_right = signal_processor.add_frequency_resolution(cfar_right);
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Run(options) => process(options.config()?, None, true, true),
+        Command::Headless(options) => process(options.config()?, None, false, true),
+        Command::ListDevices => {
+            print_devices();
+            Ok(())
+        }
+        Command::Record {
+            options,
+            output,
+            seconds,
+        } => {
+            let config = options.config()?;
+            let (mut source, _) = open_source(&config.capture, None)?;
+            println!("Recording to {output}");
+            let written = audio::record(
+                source.as_mut(),
+                &output,
+                seconds.map(Duration::from_secs_f64),
+            )?;
+            println!(
+                "Recorded {:.1} s",
+                written as f64 / source.sample_rate() as f64
+            );
+            Ok(())
+        }
+        Command::Replay {
+            options,
+            input,
+            headless,
+            send,
+        } => process(options.config()?, Some(&input), !headless, send),
+        Command::Calibrate {
+            options,
+            angle,
+            seconds,
+            input,
+        } => {
+            let config = options.config()?;
+            let (mut source, _) = open_source(&config.capture, input.as_deref())?;
+            calibrate::run(&config, source.as_mut(), angle.to_radians(), seconds)
+        }
+    }
+}
 
-                let fft_conj_mul: Vec<Complex32> = left_fft
-                    .iter()
-                    .zip(right_fft.iter().map(|x| x.conj()))
-                    .map(|(x, y)| x * y)
-                    .collect();
+/// Opens and starts the WAV file `replay`, or the configured device without one. The device
+/// name is returned for live input.
+fn open_source(
+    capture: &CaptureSettings,
+    replay: Option<&str>,
+) -> Result<(Box<dyn AudioSource>, Option<String>)> {
+    let (mut source, device_name): (Box<dyn AudioSource>, Option<String>) = match replay {
+        Some(path) => {
+            println!("Replaying: {path}");
+            let replay = FileStreamEncapsulate::new(
+                path,
+                capture.replay_block_size,
+                capture.replay_realtime,
+            )?;
+            (Box::new(replay), None)
+        }
+        None => {
+            let selector = DeviceSelector::parse(&capture.device);
+            let stream = StreamEncapsulate::new(&selector, &capture.capture_config())?;
+            let name = stream.device_name.clone();
+            (Box::new(stream), Some(name))
+        }
+    };
+    source.start()?; // Spawned New Thread Here
 
-                // let signal_to_send = signal_processor.add_frequency_resolution(fft_conj_mul);
+    Ok((source, device_name))
+}
 
-                // for gcc phat, you have to divide the magnetude to make it "unity"
+/// Runs the direction finding on `replay` or the live input, with the plots if `show_ui`
+/// and the talkers sent to the server if `send`.
+fn process(config: Config, replay: Option<&str>, show_ui: bool, send: bool) -> Result<()> {
+    if show_ui && !cfg!(feature = "gui") {
+        return Err(Error::Gui(String::from(
+            "built without the gui feature, use headless or replay --headless",
+        )));
+    }
+
+    let (mut source, device_name) = open_source(&config.capture, replay)?;
+    let mut pipeline = Pipeline::new(&config, source.sample_rate(), source.channel_count())?;
+
+    let socket_tx = send.then(|| {
+        TcpClient::spawn(
+            config.network.server.clone(),
+            config.node.h,
+            config.node.k,
+            config.node.phi,
+            pipeline.baseline(),
+        )
+    });
 
-                let mut fft_conj_mul: Vec<Complex32> = fft_conj_mul
-                    .iter()
-                    .map(|x| x / (x.re * x.re + x.im * x.im).sqrt())
-                    .collect();
+    #[cfg(feature = "gui")]
+    if show_ui {
+        return show(&config, source, device_name, pipeline, socket_tx);
+    }
+    let _ = device_name;
 
-                let correlation = signal_processor.ifft(&mut fft_conj_mul); // this part is gcc phat
+    while let Some(channels) = source.next_block() {
+        for frame in pipeline.process(&channels) {
+            if frame.tracks.is_empty() {
+                continue;
+            }
+            match &socket_tx {
+                Some(socket_tx) => {
+                    let _ = socket_tx.try_send(frame.tracks);
+                }
+                None => {
+                    for (id, delay, _, _) in &frame.tracks {
+                        println!("{id}: {delay}");
+                    }
+                }
+            }
+        }
+    }
 
-                //                let magnetude = signal_processor.complex_signal_to_magnitude(&correlation);
-                let magnetude = signal_processor.fft_time_addition(&correlation);
+    println!("Input stream ended");
+    Ok(())
+}
 
-                let (max_time, _max_correlation) =
-                    match signal_processor.parabolic_interpolate_peak_robust(&magnetude) {
-                        Ok((a, b)) => (a, b),
-                        Err(_) => continue,
-                    };
+/// Processes on a separate thread and blocks on the window until it is closed.
+#[cfg(feature = "gui")]
+fn show(
+    config: &Config,
+    mut source: Box<dyn AudioSource>,
+    device_name: Option<String>,
+    mut pipeline: Pipeline,
+    socket_tx: Option<SyncSender<TrackReport>>,
+) -> Result<()> {
+    let devices = list_input_devices();
+    let selected = device_name.and_then(|name| devices.iter().position(|x| x.name == name));
+    let geometry = pipeline.geometry().clone();
+    let (left, right) = pipeline.reference_pair();
+    let capture_config = config.capture.capture_config();
 
-                // now fit a quardratic equation to get a better number
+    let (app_right_tx, app_right_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
+    let (app_left_tx, app_left_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
+    let (app_left_cfar_tx, app_left_cfar_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
+    let (app_right_cfar_tx, app_right_cfar_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
+    let (cross_correlation_tx, cross_correlation_rx) = mpsc::sync_channel::<Vec<(f32, f32)>>(1);
+    let (phase_tx, phase_rx) = mpsc::sync_channel::<VecDeque<f32>>(1);
+    let (quality_tx, quality_rx) = mpsc::sync_channel::<(f32, PeakQuality)>(1);
+    let (speech_tx, speech_rx) = mpsc::sync_channel::<bool>(1);
+    let (srp_tx, srp_rx) = mpsc::sync_channel::<Option<SrpMap>>(1);
+    let (music_tx, music_rx) = mpsc::sync_channel::<Option<MusicSpectrum>>(1);
+    let (tracks_tx, tracks_rx) = mpsc::sync_channel::<Vec<(u32, VecDeque<f32>)>>(1);
+    let (weighting_tx, weighting_rx) = mpsc::sync_channel::<Weighting>(1);
+    let (device_tx, device_rx) = mpsc::sync_channel::<DeviceSelector>(1);
+    let (switched_tx, switched_rx) = mpsc::sync_channel::<Result<()>>(1);
 
-                phase_queue.push_back(max_time);
+    thread::spawn(move || {
+        // Signal Processing Thread
+        loop {
+            if let Ok(selector) = device_rx.try_recv() {
+                let switched = switch_input(&selector, &capture_config, source.as_ref())
+                    .map(|stream| source = Box::new(stream));
+                if let Err(e) = &switched {
+                    eprintln!("Keeping the current input: {e}");
+                }
+                let _ = switched_tx.try_send(switched);
+            }
 
-                // phase_queue.push_back((max_correlation, max_time));
+            if let Ok(weighting) = weighting_rx.try_recv() {
+                println!("GCC weighting: {weighting}");
+                pipeline.set_weighting(weighting);
+            }
 
-                if phase_queue.len() > 120 {
-                    phase_queue.pop_front();
-                }
+            let Some(channels) = source.next_block() else {
+                println!("Input stream ended");
+                break;
+            };
 
-                if phase_queue.len() < 10 {
-                    continue;
+            for mut frame in pipeline.process(&channels) {
+                if let Some(socket_tx) = &socket_tx
+                    && !frame.tracks.is_empty()
+                {
+                    let _ = socket_tx.try_send(frame.tracks);
                 }
 
-                let phase_queue: VecDeque<f32> = filter
-                    .bidirectional(&phase_queue.iter().map(|a| *a as f64).collect())
-                    .unwrap()
-                    .iter()
-                    .map(|a| *a as f32)
-                    .collect();
-
-                let _ = socket_tx.try_send(*phase_queue.get(phase_queue.len() - 1).unwrap() as f64);
-
-                // Now implement Low Pass Filter
-                let _ = app_right_tx.try_send(right_magnitude_plot);
-                let _ = app_left_tx.try_send(left_magnitude_plot);
-                let _ = app_left_cfar_tx.try_send(cfar_left);
-                let _ = app_right_cfar_tx.try_send(cfar_right);
-                let _ = cross_correlation_tx.try_send(magnetude);
-                let _ = phase_tx.try_send(phase_queue);
+                let _ = app_right_tx.try_send(std::mem::take(&mut frame.magnitude[right]));
+                let _ = app_left_tx.try_send(std::mem::take(&mut frame.magnitude[left]));
+                let _ = app_left_cfar_tx.try_send(std::mem::take(&mut frame.cfar[left]));
+                let _ = app_right_cfar_tx.try_send(std::mem::take(&mut frame.cfar[right]));
+                let _ = cross_correlation_tx.try_send(frame.correlation);
+                let _ = phase_tx.try_send(pipeline.phases().clone());
+                let _ = speech_tx.try_send(frame.speech);
+                let _ = quality_tx.try_send((frame.confidence, frame.quality));
+                let _ = srp_tx.try_send(frame.srp);
+                let _ = music_tx.try_send(frame.music);
+                let _ = tracks_tx.try_send(pipeline.track_histories().to_vec());
             }
         }
     });
@@ -180,17 +345,64 @@ fn main() -> Result<(), eframe::Error> {
         "AudioDir",
         NativeOptions::default(),
         Box::new(move |cc| {
-            Result::Ok(Box::new(Application::new(
+            Ok(Box::new(Application::new(
                 cc,
-                app_right_rx,
-                app_left_rx,
-                app_right_cfar_rx,
-                app_left_cfar_rx,
-                phase_rx,
-                cross_correlation_rx,
+                PlotReceivers {
+                    right_rx: app_right_rx,
+                    left_rx: app_left_rx,
+                    right_cfar_rx: app_right_cfar_rx,
+                    left_cfar_rx: app_left_cfar_rx,
+                    phase_rx,
+                    cross_correlation_rx,
+                    speech_rx,
+                    quality_rx,
+                    srp_rx,
+                    music_rx,
+                    tracks_rx,
+                },
+                weighting_tx,
+                config.dsp.weighting,
+                DevicePicker::new(devices, selected, device_tx, switched_rx),
+                geometry,
+                (left, right),
             )))
         }),
-    )?;
+    )
+    .map_err(|e| Error::Gui(e.to_string()))
+}
 
-    Ok(())
+/// Opens and starts the device `selector` picks, if it runs at the sample rate and channel
+/// count of `current` the estimators are set up for.
+#[cfg(feature = "gui")]
+fn switch_input(
+    selector: &DeviceSelector,
+    capture: &CaptureConfig,
+    current: &dyn AudioSource,
+) -> Result<StreamEncapsulate> {
+    let mut stream = StreamEncapsulate::new(selector, capture)?;
+
+    if stream.sample_rate() != current.sample_rate()
+        || stream.channel_count() != current.channel_count()
+    {
+        return Err(Error::Device(format!(
+            "{} runs at {} Hz with {} channels instead of {} Hz with {}",
+            stream.device_name,
+            stream.sample_rate(),
+            stream.channel_count(),
+            current.sample_rate(),
+            current.channel_count()
+        )));
+    }
+
+    stream.start()?;
+    Ok(stream)
+}
+
+fn print_devices() {
+    for device in list_input_devices() {
+        println!("{}: [{}] {}", device.index, device.host, device.name);
+        for config in &device.configs {
+            println!("    {config}");
+        }
+    }
 }
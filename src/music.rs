@@ -0,0 +1,550 @@
+use crate::SPEED_SOUND;
+use crate::error::{Error, Result};
+use crate::geometry::ArrayGeometry;
+use rustfft::num_complex::Complex32;
+use std::f32::consts::PI;
+
+/// MUSIC pseudo-spectrum over azimuth and the directions of its strongest peaks.
+pub struct MusicSpectrum {
+    pub azimuths: Vec<f32>,
+    /// Wideband pseudo-spectrum, the mean of the per-bin spectra normalized to a peak of `1.0`.
+    pub pseudo: Vec<f32>,
+    /// Azimuths of up to `sources` peaks in radians, strongest first.
+    pub peaks: Vec<f32>,
+}
+
+/// MUSIC direction of arrival estimator for arrays of three or more microphones.
+///
+/// Every frequency bin in the band keeps a spatial covariance across the microphones,
+/// averaged over frames with exponential forgetting. The eigenvectors of its smallest
+/// eigenvalues span the noise subspace, and directions whose steering vector is orthogonal
+/// to it show up as peaks of the narrowband pseudo-spectrum. The wideband spectrum averages
+/// the normalized narrowband spectra of the selected bins incoherently, which lets it
+/// resolve up to `microphones - 1` simultaneous talkers where GCC only reports one.
+///
+/// Azimuth is measured like [`Direction`](crate::srp::Direction), sources are assumed in the
+/// plane of the array.
+pub struct Music {
+    samples_rate: u32,
+    positions: Vec<[f64; 3]>,
+    channels: Vec<usize>,
+    azimuths: Vec<f32>,
+    band: (f32, f32),
+    sources: usize,
+    forgetting: f32,
+    fft_len: usize,
+    // covariances[k] is the row major covariance of bin `k`, empty until the first frame
+    covariances: Vec<Vec<Complex32>>,
+}
+
+impl Music {
+    pub fn new(samples_rate: u32, geometry: &ArrayGeometry, azimuth_steps: usize) -> Self {
+        let azimuth_steps = azimuth_steps.max(1);
+
+        Music {
+            samples_rate,
+            positions: geometry.microphones().iter().map(|x| x.position).collect(),
+            channels: geometry.microphones().iter().map(|x| x.channel).collect(),
+            azimuths: (0..azimuth_steps)
+                .map(|i| 2.0 * PI * i as f32 / azimuth_steps as f32 - PI)
+                .collect(),
+            band: (300.0, 4000.0),
+            sources: 1,
+            forgetting: 0.9,
+            fft_len: 0,
+            covariances: Vec::new(),
+        }
+    }
+
+    /// Number of sources to look for, at most one less than the number of microphones.
+    pub fn set_sources(&mut self, sources: usize) -> Result<()> {
+        if sources < 1 || sources >= self.channels.len() {
+            return Err(Error::Config(format!(
+                "MUSIC resolves between 1 and {} sources with {} microphones, asked for {sources}",
+                self.channels.len().saturating_sub(1),
+                self.channels.len()
+            )));
+        }
+
+        self.sources = sources;
+        Ok(())
+    }
+
+    /// Frequency range in Hz of the bins that are averaged.
+    pub fn set_band(&mut self, band: (f32, f32)) {
+        self.band = band;
+        self.covariances.clear();
+    }
+
+    /// Weight of the previous covariance when a frame is added, between `0.0` (only the
+    /// current frame) and just below `1.0` (long memory).
+    pub fn set_forgetting(&mut self, forgetting: f32) {
+        self.forgetting = forgetting.clamp(0.0, 0.999);
+    }
+
+    /// Adds one frame of every channel and returns the wideband pseudo-spectrum.
+    ///
+    /// With a detection mask only detected bins contribute to the spectrum, all bins in the
+    /// band still update their covariance. `None` if no bin was selected.
+    pub fn estimate(
+        &mut self,
+        spectra: &[Vec<Complex32>],
+        detected: Option<&[bool]>,
+    ) -> Option<MusicSpectrum> {
+        let fft_len = spectra.first()?.len();
+        if fft_len != self.fft_len {
+            self.fft_len = fft_len;
+            self.covariances.clear();
+        }
+
+        let bins = self.band_bins();
+        if self.covariances.is_empty() {
+            let size = self.channels.len() * self.channels.len();
+            self.covariances = vec![Vec::new(); bins.end];
+            for k in bins.clone() {
+                self.covariances[k] = vec![Complex32::ZERO; size];
+            }
+        }
+
+        let mut pseudo = vec![0.0; self.azimuths.len()];
+        let mut used = 0;
+
+        for k in bins {
+            let snapshot: Vec<Complex32> = self.channels.iter().map(|c| spectra[*c][k]).collect();
+            self.update_covariance(k, &snapshot);
+
+            if detected.is_some_and(|detected| !detected.get(k).copied().unwrap_or(false)) {
+                continue;
+            }
+
+            let narrowband = self.narrowband(k);
+            let high = narrowband.iter().copied().fold(0.0, f32::max);
+            if high > 0.0 && high.is_finite() {
+                pseudo
+                    .iter_mut()
+                    .zip(narrowband)
+                    .for_each(|(a, b)| *a += b / high);
+                used += 1;
+            }
+        }
+
+        if used == 0 {
+            return None;
+        }
+
+        pseudo.iter_mut().for_each(|x| *x /= used as f32);
+        let peaks = self.peaks(&pseudo);
+
+        Some(MusicSpectrum {
+            azimuths: self.azimuths.clone(),
+            pseudo,
+            peaks,
+        })
+    }
+
+    /// Narrowband pseudo-spectrum of bin `k` from its current covariance.
+    pub fn narrowband(&self, k: usize) -> Vec<f32> {
+        let n = self.channels.len();
+        let Some(covariance) = self.covariances.get(k).filter(|x| !x.is_empty()) else {
+            return vec![0.0; self.azimuths.len()];
+        };
+
+        let noise = noise_subspace(covariance, n, self.sources);
+        let frequency = k as f64 * self.samples_rate as f64 / self.fft_len as f64;
+
+        self.azimuths
+            .iter()
+            .map(|azimuth| {
+                let u = [azimuth.cos() as f64, azimuth.sin() as f64, 0.0];
+                let steering: Vec<(f64, f64)> = self
+                    .positions
+                    .iter()
+                    .map(|p| {
+                        // arrival leads by p·u / c, the FFT turns that into a phase advance
+                        let lead = (p[0] * u[0] + p[1] * u[1] + p[2] * u[2]) / SPEED_SOUND;
+                        let phase = 2.0 * std::f64::consts::PI * frequency * lead;
+                        (phase.cos(), phase.sin())
+                    })
+                    .collect();
+
+                // |e^H a|² summed over the noise eigenvectors
+                let projection: f64 = noise
+                    .iter()
+                    .map(|e| {
+                        let (re, im) = e.iter().zip(steering.iter()).fold(
+                            (0.0, 0.0),
+                            |(re, im), ((er, ei), (ar, ai))| {
+                                (re + er * ar + ei * ai, im + er * ai - ei * ar)
+                            },
+                        );
+                        re * re + im * im
+                    })
+                    .sum();
+
+                (1.0 / projection.max(1e-12)) as f32
+            })
+            .collect()
+    }
+
+    fn update_covariance(&mut self, k: usize, snapshot: &[Complex32]) {
+        let n = snapshot.len();
+        let alpha = self.forgetting;
+        let covariance = &mut self.covariances[k];
+
+        for i in 0..n {
+            for j in 0..n {
+                let outer = snapshot[i] * snapshot[j].conj();
+                covariance[i * n + j] = covariance[i * n + j] * alpha + outer * (1.0 - alpha);
+            }
+        }
+    }
+
+    fn band_bins(&self) -> std::ops::Range<usize> {
+        if self.fft_len == 0 {
+            return 0..0;
+        }
+
+        let resolution = self.samples_rate as f32 / self.fft_len as f32;
+        let (low, high) = self.band;
+
+        let low = ((low / resolution).ceil() as usize).clamp(1, self.fft_len / 2);
+        let high = ((high / resolution).floor() as usize + 1).clamp(low, self.fft_len / 2);
+
+        low..high
+    }
+
+    /// The `sources` highest local maxima of the circular pseudo-spectrum.
+    fn peaks(&self, pseudo: &[f32]) -> Vec<f32> {
+        let len = pseudo.len();
+        let mut maxima: Vec<(usize, f32)> = (0..len)
+            .filter(|i| {
+                let previous = pseudo[(i + len - 1) % len];
+                let next = pseudo[(i + 1) % len];
+                pseudo[*i] > previous && pseudo[*i] >= next
+            })
+            .map(|i| (i, pseudo[i]))
+            .collect();
+
+        maxima.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        maxima
+            .into_iter()
+            .take(self.sources)
+            .map(|(i, _)| self.azimuths[i])
+            .collect()
+    }
+}
+
+/// Noise subspace of an `n` by `n` Hermitian covariance as `(re, im)` eigenvectors.
+///
+/// The Hermitian matrix `A + jB` is embedded as the real symmetric `[[A, -B], [B, A]]`, whose
+/// eigenvalues are those of the covariance, each twice, with eigenvectors `[x; y]` for the
+/// complex eigenvector `x + jy`. The `2 (n - sources)` smallest real eigenvectors span the
+/// noise subspace, every complex direction counted twice, which only scales the projection.
+fn noise_subspace(covariance: &[Complex32], n: usize, sources: usize) -> Vec<Vec<(f64, f64)>> {
+    let size = 2 * n;
+    let mut matrix = vec![0.0f64; size * size];
+
+    for i in 0..n {
+        for j in 0..n {
+            let c = covariance[i * n + j];
+            let (re, im) = (c.re as f64, c.im as f64);
+            matrix[i * size + j] = re;
+            matrix[(i + n) * size + (j + n)] = re;
+            matrix[i * size + (j + n)] = -im;
+            matrix[(i + n) * size + j] = im;
+        }
+    }
+
+    let (values, vectors) = jacobi_eigen(matrix, size);
+
+    let mut order: Vec<usize> = (0..size).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+
+    order
+        .into_iter()
+        .take(2 * (n - sources))
+        .map(|column| {
+            (0..n)
+                .map(|row| {
+                    (
+                        vectors[row * size + column],
+                        vectors[(row + n) * size + column],
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Eigenvalues and column eigenvectors of a real symmetric `size` by `size` matrix by cyclic
+/// Jacobi rotations.
+fn jacobi_eigen(mut matrix: Vec<f64>, size: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut vectors = vec![0.0; size * size];
+    for i in 0..size {
+        vectors[i * size + i] = 1.0;
+    }
+
+    let scale: f64 = matrix
+        .iter()
+        .map(|x| x * x)
+        .sum::<f64>()
+        .max(f64::MIN_POSITIVE);
+
+    for _ in 0..50 {
+        let off_diagonal: f64 = (0..size)
+            .flat_map(|i| (0..size).filter(move |j| *j != i).map(move |j| (i, j)))
+            .map(|(i, j)| matrix[i * size + j] * matrix[i * size + j])
+            .sum();
+        if off_diagonal <= scale * 1e-24 {
+            break;
+        }
+
+        for p in 0..size {
+            for q in p + 1..size {
+                let apq = matrix[p * size + q];
+                if apq == 0.0 {
+                    continue;
+                }
+
+                let theta = (matrix[q * size + q] - matrix[p * size + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..size {
+                    let akp = matrix[k * size + p];
+                    let akq = matrix[k * size + q];
+                    matrix[k * size + p] = c * akp - s * akq;
+                    matrix[k * size + q] = s * akp + c * akq;
+                }
+                for k in 0..size {
+                    let apk = matrix[p * size + k];
+                    let aqk = matrix[q * size + k];
+                    matrix[p * size + k] = c * apk - s * aqk;
+                    matrix[q * size + k] = s * apk + c * aqk;
+                }
+                for k in 0..size {
+                    let vkp = vectors[k * size + p];
+                    let vkq = vectors[k * size + q];
+                    vectors[k * size + p] = c * vkp - s * vkq;
+                    vectors[k * size + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let values = (0..size).map(|i| matrix[i * size + i]).collect();
+    (values, vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Microphone;
+
+    const RATE: u32 = 48000;
+    const LEN: usize = 1024;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_f32(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+    }
+
+    fn square(side: f64) -> ArrayGeometry {
+        let half = side / 2.0;
+        ArrayGeometry::new(
+            [(half, half), (-half, half), (-half, -half), (half, -half)]
+                .into_iter()
+                .enumerate()
+                .map(|(channel, (x, y))| Microphone {
+                    channel,
+                    position: [x, y, 0.0],
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    /// Spectra of one frame of uncorrelated broadband sources arriving from `azimuths` as
+    /// plane waves, with noise 30 dB below each source on every channel.
+    fn frame(geometry: &ArrayGeometry, azimuths: &[f32], rng: &mut Rng) -> Vec<Vec<Complex32>> {
+        let mut spectra = vec![vec![Complex32::ZERO; LEN]; geometry.required_channels()];
+
+        for azimuth in azimuths {
+            let u = [azimuth.cos() as f64, azimuth.sin() as f64];
+            let phases: Vec<f32> = (0..LEN / 2).map(|_| 2.0 * PI * rng.next_f32()).collect();
+
+            for mic in geometry.microphones() {
+                let lead = (mic.position[0] * u[0] + mic.position[1] * u[1]) / SPEED_SOUND;
+                for k in 1..LEN / 2 {
+                    let advance = 2.0 * PI * k as f32 * RATE as f32 * lead as f32 / LEN as f32;
+                    spectra[mic.channel][k] += Complex32::from_polar(1.0, phases[k] + advance);
+                }
+            }
+        }
+
+        for spectrum in spectra.iter_mut() {
+            for x in spectrum[1..LEN / 2].iter_mut() {
+                *x += Complex32::from_polar(0.03, 2.0 * PI * rng.next_f32());
+            }
+        }
+
+        spectra
+    }
+
+    fn estimate(
+        geometry: &ArrayGeometry,
+        sources: usize,
+        azimuths: &[f32],
+        frames: usize,
+    ) -> MusicSpectrum {
+        let mut music = Music::new(RATE, geometry, 72);
+        music.set_sources(sources).unwrap();
+
+        let mut rng = Rng(0x5EED);
+        let mut spectrum = None;
+        for _ in 0..frames {
+            spectrum = music.estimate(&frame(geometry, azimuths, &mut rng), None);
+        }
+        spectrum.unwrap()
+    }
+
+    fn assert_near(found: f32, expected: f32) {
+        let error = crate::angle_wrap_f32(found - expected).abs();
+        assert!(
+            error < 1e-4,
+            "found {} expected {}",
+            found.to_degrees(),
+            expected.to_degrees()
+        );
+    }
+
+    #[test]
+    fn jacobi_diagonalizes_symmetric_matrices() {
+        let size = 6;
+        let mut rng = Rng(7);
+        let mut matrix = vec![0.0f64; size * size];
+        for i in 0..size {
+            for j in 0..=i {
+                let x = rng.next_f32() as f64 - 0.5;
+                matrix[i * size + j] = x;
+                matrix[j * size + i] = x;
+            }
+        }
+
+        let (values, vectors) = jacobi_eigen(matrix.clone(), size);
+
+        for column in 0..size {
+            for row in 0..size {
+                // A v = λ v
+                let av: f64 = (0..size)
+                    .map(|k| matrix[row * size + k] * vectors[k * size + column])
+                    .sum();
+                let lv = values[column] * vectors[row * size + column];
+                assert!((av - lv).abs() < 1e-9, "{column} {row}");
+            }
+
+            // orthonormal columns
+            for other in 0..size {
+                let dot: f64 = (0..size)
+                    .map(|k| vectors[k * size + column] * vectors[k * size + other])
+                    .sum();
+                let expected = if other == column { 1.0 } else { 0.0 };
+                assert!((dot - expected).abs() < 1e-9, "{column} {other}");
+            }
+        }
+
+        // a matrix with a repeated eigenvalue
+        let (mut values, _) = jacobi_eigen(vec![2.0, 1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0, 3.0], 3);
+        values.sort_by(f64::total_cmp);
+        for (value, expected) in values.iter().zip([1.0, 3.0, 3.0]) {
+            assert!((value - expected).abs() < 1e-12, "{values:?}");
+        }
+    }
+
+    #[test]
+    fn noise_subspace_is_orthogonal_to_the_source() {
+        let n = 3;
+        let steering: Vec<Complex32> = [0.0f32, 1.3, -2.1]
+            .iter()
+            .map(|phase| Complex32::from_polar(1.0, *phase))
+            .collect();
+
+        // a a^H plus a little white noise
+        let covariance: Vec<Complex32> = (0..n * n)
+            .map(|x| {
+                let (i, j) = (x / n, x % n);
+                let noise = if i == j { 0.01 } else { 0.0 };
+                steering[i] * steering[j].conj() + noise
+            })
+            .collect();
+
+        let noise = noise_subspace(&covariance, n, 1);
+        assert_eq!(noise.len(), 2 * (n - 1));
+
+        for e in &noise {
+            let norm: f64 = e.iter().map(|(re, im)| re * re + im * im).sum();
+            assert!((norm - 1.0).abs() < 1e-6, "{norm}");
+
+            // e^H a
+            let (re, im) = e
+                .iter()
+                .zip(&steering)
+                .fold((0.0, 0.0), |(re, im), ((er, ei), a)| {
+                    let (ar, ai) = (a.re as f64, a.im as f64);
+                    (re + er * ar + ei * ai, im + er * ai - ei * ar)
+                });
+            assert!(re * re + im * im < 1e-8, "{re} {im}");
+        }
+    }
+
+    #[test]
+    fn steering_vector_points_at_the_source() {
+        // a sign error in the steering phase would mirror the peak to -140 degrees
+        let geometry = square(0.2);
+        let azimuth = 40f32.to_radians();
+        let spectrum = estimate(&geometry, 1, &[azimuth], 20);
+
+        assert_eq!(spectrum.peaks.len(), 1);
+        assert_near(spectrum.peaks[0], azimuth);
+    }
+
+    #[test]
+    fn square_array_resolves_two_talkers() {
+        let geometry = square(0.2);
+        let azimuths = [40f32.to_radians(), -100f32.to_radians()];
+        let spectrum = estimate(&geometry, 2, &azimuths, 50);
+
+        assert_eq!(spectrum.peaks.len(), 2);
+        let mut peaks = spectrum.peaks.clone();
+        peaks.sort_by(|a, b| b.total_cmp(a));
+        assert_near(peaks[0], azimuths[0]);
+        assert_near(peaks[1], azimuths[1]);
+    }
+
+    #[test]
+    fn two_microphones_resolve_one_source() {
+        let geometry = ArrayGeometry::two_mic_bar(0.2).unwrap();
+        let mut music = Music::new(RATE, &geometry, 72);
+
+        assert!(matches!(music.set_sources(0), Err(Error::Config(_))));
+        assert!(matches!(music.set_sources(2), Err(Error::Config(_))));
+        music.set_sources(1).unwrap();
+
+        // the bar sees 60 and -60 degrees alike
+        let spectrum = estimate(&geometry, 1, &[60f32.to_radians()], 20);
+        assert_eq!(spectrum.peaks.len(), 1);
+        assert!(
+            (spectrum.peaks[0].abs() - 60f32.to_radians()).abs() < 1e-4,
+            "{}",
+            spectrum.peaks[0].to_degrees()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::TrackReport;
+use crate::error::{Error, Result};
+use crate::gcc::PeakQuality;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub struct TcpClient {
+    route: String,
+    stream: Option<TcpStream>,
+    pub h: f64,
+    pub k: f64,
+    pub phi: f64,
+    pub mic_dis: f64,
+    pub del_t: f64,
+    pub confidence: f32,
+    pub quality: PeakQuality,
+    pub timestamp: u64,
+    /// Track the delay belongs to, see [`MultiTargetTracker`](crate::tracking::MultiTargetTracker).
+    pub track_id: u32,
+}
+
+impl TcpClient {
+    pub fn new(route: String, h: f64, k: f64, phi: f64, mic_dis: f64) -> Self {
+        let mut client = TcpClient {
+            route,
+            stream: None,
+            h,
+            k,
+            phi,
+            mic_dis,
+            del_t: 0.0,
+            confidence: 0.0,
+            quality: PeakQuality::default(),
+            timestamp: 0,
+            track_id: 0,
+        };
+
+        client.connect();
+        client
+    }
+
+    fn connect(&mut self) {
+        loop {
+            match TcpStream::connect(&self.route) {
+                Ok(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+                    println!("Connected to {}", self.route);
+                    self.stream = Some(stream);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Connect failed: {e}, retrying...");
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    /// Sends the current values as one CSV line of twelve columns:
+    ///
+    /// ```text
+    /// timestamp,h,k,phi,mic_dis,del_t,confidence,peak,peak_to_second,peak_to_mean,sharpness,track_id
+    /// ```
+    ///
+    /// `timestamp` is in milliseconds since the Unix epoch, `h`, `k` and `phi` are the node
+    /// pose, `mic_dis` the microphone spacing in metres and `del_t` the delay in seconds. The
+    /// four quality columns are the [`PeakQuality`] of the peak the track was last updated
+    /// with.
+    ///
+    /// A failed write drops the connection and reconnects before the error is returned, so
+    /// the next call can go through.
+    pub fn send(&mut self) -> Result<()> {
+        let data = format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            self.timestamp,
+            self.h,
+            self.k,
+            self.phi,
+            self.mic_dis,
+            self.del_t,
+            self.confidence,
+            self.quality.peak,
+            self.quality.peak_to_second,
+            self.quality.peak_to_mean,
+            self.quality.sharpness,
+            self.track_id
+        );
+
+        if let Some(stream) = self.stream.as_mut() {
+            if let Err(e) = stream
+                .write_all(data.as_bytes())
+                .and_then(|_| stream.flush())
+            {
+                self.stream = None; // drop broken stream
+                self.connect(); // reconnect
+                return Err(Error::Network(e));
+            }
+        } else {
+            self.connect();
+        }
+
+        Ok(())
+    }
+
+    /// Connects on a new thread and sends every batch of talkers received on the returned
+    /// channel, one line per talker with a shared timestamp. The thread ends when the sender
+    /// is dropped.
+    pub fn spawn(route: String, h: f64, k: f64, phi: f64, mic_dis: f64) -> SyncSender<TrackReport> {
+        let (tx, rx) = mpsc::sync_channel::<TrackReport>(1);
+
+        thread::spawn(move || {
+            let mut client = TcpClient::new(route, h, k, phi, mic_dis);
+
+            while let Ok(tracks) = rx.recv() {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+
+                for (id, val, confidence, quality) in tracks {
+                    client.track_id = id;
+                    client.del_t = val;
+                    client.confidence = confidence;
+                    client.quality = quality;
+                    println!("{id}: {val}");
+                    client.timestamp = timestamp;
+                    if let Err(e) = client.send() {
+                        eprintln!("{e}");
+                    }
+                }
+            }
+        });
+
+        tx
+    }
+}
@@ -0,0 +1,435 @@
+use crate::config::{CfarSettings, Config};
+use crate::error::{Error, Result};
+use crate::frame::Framer;
+use crate::gcc::{self, PairwiseGcc, PeakQuality, TdoaEstimate, Weighting};
+use crate::geometry::ArrayGeometry;
+use crate::music::{Music, MusicSpectrum};
+use crate::signal::SignalProcessor;
+use crate::srp::{SrpMap, SrpPhat};
+use crate::tracking::{AngleTracker, MultiTargetTracker, PostFilter, Track};
+use crate::vad::VoiceActivityDetector;
+use crate::{TrackReport, cfar_detections};
+use rustfft::num_complex::Complex32;
+use std::collections::{HashMap, VecDeque};
+
+/// Everything one frame produced, for the network output and for displays.
+pub struct FrameOutput {
+    /// dB magnitude spectrum of every channel.
+    pub magnitude: Vec<Vec<(f32, f32)>>,
+    /// CFAR threshold of every channel.
+    pub cfar: Vec<Vec<(f32, f32)>>,
+    /// GCC of the reference pair.
+    pub correlation: Vec<(f32, f32)>,
+    pub speech: bool,
+    pub confidence: f32,
+    pub quality: PeakQuality,
+    pub srp: Option<SrpMap>,
+    pub music: Option<MusicSpectrum>,
+    /// Id, reference pair delay in seconds, confidence and peak quality of every confirmed
+    /// talker, empty while nobody talks.
+    pub tracks: TrackReport,
+}
+
+/// Capture to tracks processing of a microphone array, independent of any display.
+///
+/// Blocks of every channel are framed, transformed, gated by CFAR and the VAD and turned
+/// into pairwise GCC, SRP and MUSIC estimates. The reference pair drives a single Kalman
+/// track for the angle history and a multi-target tracker whose talkers are reported.
+pub struct Pipeline {
+    geometry: ArrayGeometry,
+    left: usize,
+    right: usize,
+    baseline: f64,
+    signal_processor: SignalProcessor,
+    framers: Vec<Framer>,
+    cfar: CfarSettings,
+    vad: VoiceActivityDetector,
+    estimator: PairwiseGcc,
+    srp: SrpPhat,
+    music: Option<Music>,
+    frame_interval: f32,
+    tracker: AngleTracker,
+    post_filter: PostFilter,
+    // last tracked angle, the post-filter keeps running on it through silence
+    held_angle: Option<f32>,
+    talkers: MultiTargetTracker,
+    talker_filter: PostFilter,
+    // post-filter, last angle and quality of the last peak of every talker
+    talker_filters: HashMap<u32, (PostFilter, f32, PeakQuality)>,
+    history_len: usize,
+    phase_queue: VecDeque<f32>,
+    // angle history of every talker tracked from the correlation peaks, NaN while it is quiet
+    track_queues: Vec<(u32, VecDeque<f32>)>,
+}
+
+impl Pipeline {
+    /// Sets up every estimator of `config` for an input of `channel_count` channels at
+    /// `samples_rate`.
+    pub fn new(config: &Config, samples_rate: u32, channel_count: usize) -> Result<Self> {
+        let Config {
+            array,
+            dsp,
+            tracking,
+            ui,
+            ..
+        } = config;
+
+        let geometry = array.geometry()?;
+
+        let signal_processor = SignalProcessor::new(samples_rate);
+
+        println!(
+            "The time resolution is: {}",
+            signal_processor.get_time_resolution()
+        );
+
+        println!("Channels: {channel_count}");
+        if geometry.required_channels() > channel_count {
+            return Err(Error::Config(format!(
+                "Array geometry uses {} channels but the input only has {channel_count}",
+                geometry.required_channels()
+            )));
+        }
+
+        // the angle plot and the TCP output follow the first two microphones of the array
+        let (left, right) = geometry.reference_pair();
+        let missing_pair = || {
+            Error::Config(format!(
+                "Array geometry has no microphones on channels {left} and {right}"
+            ))
+        };
+        let baseline = geometry.distance(left, right).ok_or_else(missing_pair)?;
+
+        let angle_resolution = geometry
+            .angle_resolution(left, right, signal_processor.get_time_resolution())
+            .ok_or_else(missing_pair)?;
+        println!(
+            "angle_resolution: {} degrees",
+            angle_resolution * 180.0 / std::f32::consts::PI
+        );
+
+        let mut estimator = PairwiseGcc::new(samples_rate, &geometry);
+        estimator.set_weighting(dsp.weighting);
+        estimator.set_band(dsp.voice_band);
+        estimator.set_min_bins(dsp.cfar.min_bins);
+        for a in geometry.microphones() {
+            for b in geometry.microphones() {
+                if let Some(spacing) = geometry.distance(a.channel, b.channel) {
+                    estimator.set_max_lag(
+                        a.channel,
+                        b.channel,
+                        Some(gcc::max_lag(spacing, dsp.lag_margin)),
+                    );
+                }
+            }
+        }
+
+        // elevation is only scanned when the microphones aren't all in one plane
+        let srp = SrpPhat::new(
+            &geometry,
+            dsp.srp_azimuth_steps,
+            if geometry.is_planar() {
+                1
+            } else {
+                dsp.srp_elevation_steps
+            },
+        );
+
+        // MUSIC needs more microphones than sources, a bar of two only gets GCC and SRP
+        let music = if geometry.microphones().len() >= 3 {
+            let mut music = Music::new(samples_rate, &geometry, dsp.srp_azimuth_steps);
+            music.set_sources(dsp.music_sources.min(geometry.microphones().len() - 1))?;
+            if let Some(band) = dsp.voice_band {
+                music.set_band(band);
+            }
+            Some(music)
+        } else {
+            None
+        };
+
+        let framers: Vec<Framer> = (0..channel_count)
+            .map(|_| Framer::new(dsp.frame_len, dsp.frame_hop, dsp.window))
+            .collect::<Result<_>>()?;
+        println!(
+            "Frames: {} samples, hop {}, {} window",
+            dsp.frame_len, dsp.frame_hop, dsp.window
+        );
+
+        let mut vad = VoiceActivityDetector::new(samples_rate, dsp.vad);
+        if let Some(band) = dsp.voice_band {
+            vad.set_band(band);
+        }
+
+        // one estimate per frame, whatever block size the device delivers
+        let frame_interval = dsp.frame_hop as f32 / samples_rate as f32;
+        let estimate_rate = PostFilter::estimate_rate(samples_rate, dsp.frame_hop);
+        println!(
+            "Estimates: {estimate_rate} per second, smoothed below {} Hz",
+            tracking.post_filter_cutoff
+        );
+
+        let mut tracker = AngleTracker::new(tracking.process_noise, tracking.measurement_noise);
+        tracker.set_gate(tracking.gate);
+        let mut talkers =
+            MultiTargetTracker::new(tracking.process_noise, tracking.measurement_noise);
+        talkers.set_gate(tracking.gate);
+        talkers.set_birth(tracking.birth.0, tracking.birth.1)?;
+        talkers.set_max_misses(tracking.max_misses);
+        talkers.set_max_coast(tracking.max_coast);
+        let post_filter = PostFilter::new(estimate_rate, tracking.post_filter_cutoff)?;
+
+        Ok(Pipeline {
+            geometry,
+            left,
+            right,
+            baseline,
+            signal_processor,
+            framers,
+            cfar: dsp.cfar.clone(),
+            vad,
+            estimator,
+            srp,
+            music,
+            frame_interval,
+            tracker,
+            talker_filter: post_filter.clone(),
+            post_filter,
+            held_angle: None,
+            talkers,
+            talker_filters: HashMap::new(),
+            history_len: ui.history_len,
+            phase_queue: VecDeque::new(),
+            track_queues: Vec::new(),
+        })
+    }
+
+    pub fn geometry(&self) -> &ArrayGeometry {
+        &self.geometry
+    }
+
+    /// Channels of the pair the delays, the angle history and the network output refer to.
+    pub fn reference_pair(&self) -> (usize, usize) {
+        (self.left, self.right)
+    }
+
+    /// Spacing of the reference pair in metres.
+    pub fn baseline(&self) -> f64 {
+        self.baseline
+    }
+
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.estimator.set_weighting(weighting);
+    }
+
+    /// Tracked delays of the reference pair during speech, oldest first.
+    pub fn phases(&self) -> &VecDeque<f32> {
+        &self.phase_queue
+    }
+
+    /// Track id and angle history of every talker, NaN while that talker is quiet.
+    pub fn track_histories(&self) -> &[(u32, VecDeque<f32>)] {
+        &self.track_queues
+    }
+
+    /// Processes one block of every channel and returns the frames it completed, frames
+    /// without a reference pair estimate are left out.
+    pub fn process(&mut self, channels: &[Vec<Complex32>]) -> Vec<FrameOutput> {
+        let mut frames: Vec<_> = self
+            .framers
+            .iter_mut()
+            .zip(channels.iter())
+            .map(|(framer, block)| framer.push(block).into_iter())
+            .collect();
+
+        let mut outputs = Vec::new();
+
+        // one frame of every channel at a time
+        while let Some(frame) = frames
+            .iter_mut()
+            .map(|frames| frames.next())
+            .collect::<Option<Vec<Vec<Complex32>>>>()
+        {
+            if let Some(output) = self.process_frame(frame) {
+                outputs.push(output);
+            }
+        }
+
+        outputs
+    }
+
+    fn process_frame(&mut self, frame: Vec<Vec<Complex32>>) -> Option<FrameOutput> {
+        let (left, right) = (self.left, self.right);
+
+        let spectra: Vec<Vec<Complex32>> = frame
+            .into_iter()
+            .map(|mut data| self.signal_processor.fft(&mut data))
+            .collect();
+
+        let magnitude: Vec<Vec<(f32, f32)>> = spectra
+            .iter()
+            .map(|x| self.signal_processor.complex_fft_to_db_magnitude(x))
+            .collect();
+
+        let cfar: Vec<Vec<(f32, f32)>> = magnitude
+            .iter()
+            .map(|plot| {
+                let var: Vec<f32> = plot.iter().map(|(_x, y)| *y).collect();
+                let cfar =
+                    SignalProcessor::cfar(&var, self.cfar.gap, self.cfar.reference, self.cfar.bias);
+                self.signal_processor.add_frequency_resolution(cfar)
+            })
+            .collect();
+
+        // keep the bins any microphone detected
+        let detected: Vec<bool> = magnitude
+            .iter()
+            .zip(cfar.iter())
+            .map(|(plot, cfar)| cfar_detections(plot, cfar))
+            .collect::<Result<Vec<_>>>()
+            .ok()?
+            .into_iter()
+            .reduce(|a, b| a.iter().zip(b).map(|(a, b)| *a || b).collect())
+            .unwrap_or_default();
+        let gating = self.cfar.gating.then_some(detected.as_slice());
+
+        let speech = self.vad.is_speech(&spectra[left], Some(&detected));
+
+        let mut matrix = self.estimator.estimate_from_spectra(&spectra, gating);
+
+        let srp = self.srp.scan(&matrix);
+        let music = self
+            .music
+            .as_mut()
+            .and_then(|music| music.estimate(&spectra, gating));
+
+        self.tracker.predict(self.frame_interval);
+        self.talkers.predict(self.frame_interval);
+
+        let estimate = matrix.take(left, right)?;
+        let confidence = estimate.confidence;
+
+        // only directions of someone talking update the track and the history
+        if speech {
+            if let Some(angle) = self.geometry.delay_to_angle(left, right, estimate.delay) {
+                self.tracker.update(angle, confidence);
+            }
+            self.held_angle = self.tracker.angle();
+        }
+
+        // the post-filters run every frame, the rate they are designed for, holding their
+        // last input through silence
+        let smoothed = self.held_angle.map(|angle| self.post_filter.process(angle));
+        let tracked = smoothed
+            .filter(|_| speech)
+            .and_then(|angle| self.geometry.angle_to_delay(left, right, angle));
+
+        if let Some(delay) = tracked {
+            self.phase_queue.push_back(delay);
+
+            if self.phase_queue.len() > self.history_len {
+                self.phase_queue.pop_front();
+            }
+        }
+
+        let tracks = if speech {
+            self.update_talkers(&estimate)
+        } else {
+            for (filter, angle, _) in self.talker_filters.values_mut() {
+                filter.process(*angle);
+            }
+            Vec::new()
+        };
+
+        Some(FrameOutput {
+            magnitude,
+            cfar,
+            correlation: estimate.correlation,
+            speech,
+            confidence,
+            quality: estimate.quality,
+            srp,
+            music,
+            tracks,
+        })
+    }
+
+    /// Feeds the correlation peaks of a speech frame to the multi-target tracker and returns
+    /// the delays of the confirmed talkers with the quality of the peak each was last
+    /// updated with.
+    fn update_talkers(&mut self, estimate: &TdoaEstimate) -> TrackReport {
+        let (left, right) = (self.left, self.right);
+
+        // weaker peaks get a proportionally lower confidence than the main one
+        let strongest = estimate.peaks.first().map_or(1.0, |(_, x)| *x);
+        let (detections, qualities): (Vec<(f32, f32)>, Vec<PeakQuality>) = estimate
+            .peaks
+            .iter()
+            .zip(&estimate.peak_qualities)
+            .filter_map(|((delay, strength), quality)| {
+                let angle = self.geometry.delay_to_angle(left, right, *delay)?;
+                Some((
+                    (angle, estimate.confidence * strength / strongest),
+                    *quality,
+                ))
+            })
+            .unzip();
+
+        let mut tracks = self.talkers.update(&detections);
+
+        self.talker_filters
+            .retain(|id, _| tracks.iter().any(|x| x.id == *id));
+        for track in tracks.iter_mut() {
+            let (filter, held, quality) =
+                self.talker_filters.entry(track.id).or_insert_with(|| {
+                    (
+                        self.talker_filter.clone(),
+                        track.angle,
+                        PeakQuality::default(),
+                    )
+                });
+            *held = track.angle;
+            if let Some(detection) = track.detection {
+                *quality = qualities[detection];
+            }
+            track.angle = filter.process(track.angle);
+        }
+
+        self.record_tracks(&tracks);
+
+        tracks
+            .iter()
+            .filter_map(|track| {
+                let delay = self.geometry.angle_to_delay(left, right, track.angle)?;
+                let (_, _, quality) = self.talker_filters[&track.id];
+                Some((track.id, delay as f64, track.confidence, quality))
+            })
+            .collect()
+    }
+
+    /// Appends the angle of every confirmed track to its history, NaN for histories whose
+    /// track wasn't updated, and forgets histories that are NaN all along.
+    fn record_tracks(&mut self, tracks: &[Track]) {
+        let queues = &mut self.track_queues;
+        let len = queues.iter().map(|(_, x)| x.len()).max().unwrap_or(0);
+
+        for track in tracks {
+            if !queues.iter().any(|(id, _)| *id == track.id) {
+                queues.push((track.id, std::iter::repeat_n(f32::NAN, len).collect()));
+            }
+        }
+
+        for (id, queue) in queues.iter_mut() {
+            let angle = tracks
+                .iter()
+                .find(|x| x.id == *id)
+                .map_or(f32::NAN, |x| x.angle);
+
+            queue.push_back(angle);
+            if queue.len() > self.history_len {
+                queue.pop_front();
+            }
+        }
+
+        queues.retain(|(_, queue)| queue.iter().any(|x| x.is_finite()));
+    }
+}
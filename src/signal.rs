@@ -1,6 +1,34 @@
+use crate::error::{Error, Result};
 use rustfft::FftPlanner;
 use rustfft::num_complex::Complex32;
 
+/// Settings of [`SignalProcessor::find_peaks`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeakSearch {
+    /// Most peaks reported, strongest first.
+    pub max_peaks: usize,
+    /// Fewest samples between two reported peaks, weaker peaks closer than this are dropped.
+    pub min_separation: usize,
+    /// Guard cells either side of a candidate, skipped when averaging the reference cells.
+    pub gap: usize,
+    /// Reference cells either side of a candidate.
+    pub refrence: usize,
+    /// A peak has to exceed `bias` times the mean absolute value of its reference cells.
+    pub bias: f32,
+}
+
+impl Default for PeakSearch {
+    fn default() -> Self {
+        PeakSearch {
+            max_peaks: 3,
+            min_separation: 4,
+            gap: 2,
+            refrence: 16,
+            bias: 4.0,
+        }
+    }
+}
+
 pub struct SignalProcessor {
     planner: FftPlanner<f32>,
     samples_rate: u32,
@@ -14,25 +42,24 @@ impl SignalProcessor {
         }
     }
 
-    pub fn fft(&mut self, array: &mut Vec<Complex32>) -> Vec<Complex32> {
+    pub fn fft(&mut self, array: &mut [Complex32]) -> Vec<Complex32> {
         let fft = self.planner.plan_fft_forward(array.len());
         fft.process(array);
-        return array.clone();
+        array.to_vec()
     }
 
-    pub fn ifft(&mut self, array: &mut Vec<Complex32>) -> Vec<Complex32> {
+    pub fn ifft(&mut self, array: &mut [Complex32]) -> Vec<Complex32> {
         let len = array.len();
         let fft = self.planner.plan_fft_inverse(array.len());
         fft.process(array);
         // normalize
-        for i in 0..array.len() {
-            // I can't do that with iter
-            array[i] /= len as f32;
+        for x in array.iter_mut() {
+            *x /= len as f32;
         }
-        return array.clone();
+        array.to_vec()
     }
 
-    pub fn complex_fft_to_db_magnitude(&mut self, array: &Vec<Complex32>) -> Vec<(f32, f32)> {
+    pub fn complex_fft_to_db_magnitude(&mut self, array: &[Complex32]) -> Vec<(f32, f32)> {
         let resolution = self.get_fft_frequency_resolution(array.len());
         array
             .iter()
@@ -46,7 +73,7 @@ impl SignalProcessor {
             .collect()
     }
 
-    pub fn complex_signal_to_magnitude(&mut self, array: &Vec<Complex32>) -> Vec<(f32, f32)> {
+    pub fn complex_signal_to_magnitude(&mut self, array: &[Complex32]) -> Vec<(f32, f32)> {
         let resolution = self.get_time_resolution();
         array
             .iter()
@@ -55,7 +82,7 @@ impl SignalProcessor {
             .collect()
     }
 
-    pub fn complex_signal_to_real_only(&mut self, array: &Vec<Complex32>) -> Vec<(f32, f32)> {
+    pub fn complex_signal_to_real_only(&mut self, array: &[Complex32]) -> Vec<(f32, f32)> {
         let resolution = self.get_time_resolution();
         array
             .iter()
@@ -64,7 +91,7 @@ impl SignalProcessor {
             .collect()
     }
 
-    // pub fn fft_time_addition(&mut self, array: &Vec<Complex32>) -> Vec<(f32, f32)> {
+    // pub fn fft_time_addition(&mut self, array: &[Complex32]) -> Vec<(f32, f32)> {
     //     let resolution = self.get_time_resolution();
 
     //     // split the array in halves
@@ -94,7 +121,7 @@ impl SignalProcessor {
     //     return full_array;
     // }
 
-    pub fn fft_time_addition(&mut self, array: &Vec<Complex32>) -> Vec<(f32, f32)> {
+    pub fn fft_time_addition(&mut self, array: &[Complex32]) -> Vec<(f32, f32)> {
         let resolution = self.get_time_resolution();
         let n = array.len();
 
@@ -102,13 +129,13 @@ impl SignalProcessor {
         let mut shifted = vec![Complex32::new(0.0, 0.0); n];
         let half = n / 2;
 
-        if n % 2 == 0 {
+        if n.is_multiple_of(2) {
             // Even length (N=8): [0,1,2,3,4,5,6,7] -> [4,5,6,7,0,1,2,3]
             shifted[..half].copy_from_slice(&array[half..]); // Second half to first
             shifted[half..].copy_from_slice(&array[..half]); // First half to second
         } else {
             // Odd length (N=7): [0,1,2,3,4,5,6] -> [4,5,6,0,1,2,3]
-            let first_part_len = (n + 1) / 2; // 4 for N=7
+            let first_part_len = n.div_ceil(2); // 4 for N=7
             let second_part_len = n / 2; // 3 for N=7
 
             // Copy second part first (indices first_part_len to end)
@@ -132,9 +159,9 @@ impl SignalProcessor {
     pub fn parabolic_interpolate_peak_robust(
         &self,
         magnetude: &[(f32, f32)],
-    ) -> Result<(f32, f32), &'static str> {
+    ) -> Result<(f32, f32)> {
         if magnetude.len() < 3 {
-            return Err("Need at least 3 points for interpolation");
+            return Err(Error::Dsp("Need at least 3 points for interpolation"));
         }
 
         // Find peak index
@@ -149,9 +176,110 @@ impl SignalProcessor {
             },
         );
 
+        self.interpolate_peak_at(magnetude, max_index)
+    }
+
+    /// Like [`Self::parabolic_interpolate_peak_robust`] but only considers lags within
+    /// `±max_lag` seconds, a peak interpolated outside the window is rejected.
+    pub fn parabolic_interpolate_peak_in_window(
+        &self,
+        magnetude: &[(f32, f32)],
+        max_lag: f32,
+    ) -> Result<(f32, f32)> {
+        if magnetude.len() < 3 {
+            return Err(Error::Dsp("Need at least 3 points for interpolation"));
+        }
+
+        // Find peak index inside the window
+        let max_index = magnetude
+            .iter()
+            .enumerate()
+            .filter(|(_, (t, _))| t.abs() <= max_lag)
+            .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))
+            .map(|(i, _)| i)
+            .ok_or(Error::Dsp("No samples inside the lag window"))?;
+
+        let (peak_time, peak_value) = self.interpolate_peak_at(magnetude, max_index)?;
+
+        if peak_time.abs() > max_lag {
+            return Err(Error::Dsp("Peak outside the lag window"));
+        }
+
+        Ok((peak_time, peak_value))
+    }
+
+    /// Local maxima of the correlation that stand out of their neighbourhood, as interpolated
+    /// `(lag, value)` pairs, strongest first.
+    ///
+    /// Like [`Self::cfar`] every candidate is compared to the cells around it, so a weak peak
+    /// on a flat correlation counts while a bump on the slope of a stronger one doesn't. Only
+    /// lags within `±max_lag` seconds are searched when a window is given.
+    pub fn find_peaks(
+        &self,
+        magnetude: &[(f32, f32)],
+        search: &PeakSearch,
+        max_lag: Option<f32>,
+    ) -> Vec<(f32, f32)> {
+        let len = magnetude.len();
+        if len < 3 {
+            return Vec::new();
+        }
+
+        let in_window = |t: f32| max_lag.is_none_or(|max_lag| t.abs() <= max_lag);
+
+        let mut candidates: Vec<usize> = (1..len - 1)
+            .filter(|i| {
+                let (t, y) = magnetude[*i];
+                in_window(t) && y > magnetude[i - 1].1 && y >= magnetude[i + 1].1
+            })
+            .filter(|i| {
+                let left =
+                    i.saturating_sub(search.gap + search.refrence)..i.saturating_sub(search.gap);
+                let right =
+                    (i + search.gap + 1).min(len)..(i + search.gap + 1 + search.refrence).min(len);
+                let reference: Vec<f32> = left.chain(right).map(|j| magnetude[j].1.abs()).collect();
+
+                if reference.is_empty() {
+                    return true;
+                }
+
+                let mean = reference.iter().sum::<f32>() / reference.len() as f32;
+                magnetude[*i].1 > search.bias * mean
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| magnetude[*b].1.total_cmp(&magnetude[*a].1));
+
+        let mut peaks: Vec<usize> = Vec::new();
+        for i in candidates {
+            if peaks.len() >= search.max_peaks {
+                break;
+            }
+            if peaks.iter().all(|p| p.abs_diff(i) >= search.min_separation) {
+                peaks.push(i);
+            }
+        }
+
+        peaks
+            .into_iter()
+            .filter_map(|i| self.interpolate_peak_at(magnetude, i).ok())
+            .filter(|(t, _)| in_window(*t))
+            .collect()
+    }
+
+    /// Parabolic interpolation around the local maximum at `max_index`.
+    pub fn interpolate_peak_at(
+        &self,
+        magnetude: &[(f32, f32)],
+        max_index: usize,
+    ) -> Result<(f32, f32)> {
+        if magnetude.len() < 3 {
+            return Err(Error::Dsp("Need at least 3 points for interpolation"));
+        }
+
         // Check peak is not at edges
-        if max_index == 0 || max_index == magnetude.len() - 1 {
-            return Err("Peak at boundary, cannot interpolate");
+        if max_index == 0 || max_index >= magnetude.len() - 1 {
+            return Err(Error::Dsp("Peak at boundary, cannot interpolate"));
         }
 
         let (t_left, y_left) = magnetude[max_index - 1];
@@ -160,7 +288,7 @@ impl SignalProcessor {
 
         // Verify this is actually a peak
         if y_center <= y_left || y_center <= y_right {
-            return Err("Not a valid peak (neighbors are higher)");
+            return Err(Error::Dsp("Not a valid peak (neighbors are higher)"));
         }
 
         // Calculate time step (should be uniform)
@@ -168,7 +296,7 @@ impl SignalProcessor {
         let time_step2 = t_right - t_center;
 
         if (time_step1 - time_step2).abs() > 1e-6 {
-            return Err("Non-uniform time spacing");
+            return Err(Error::Dsp("Non-uniform time spacing"));
         }
 
         let time_step = time_step1;
@@ -177,7 +305,7 @@ impl SignalProcessor {
         let denominator = y_left - 2.0 * y_center + y_right;
 
         if denominator.abs() < 1e-10 {
-            return Err("Denominator too small for interpolation");
+            return Err(Error::Dsp("Denominator too small for interpolation"));
         }
 
         let offset = (time_step / 2.0) * (y_left - y_right) / denominator;
@@ -196,7 +324,7 @@ impl SignalProcessor {
         Ok((peak_time, peak_value))
     }
 
-    pub fn complex_fft_to_phase_radians(&mut self, array: &Vec<Complex32>) -> Vec<(f32, f32)> {
+    pub fn complex_fft_to_phase_radians(&mut self, array: &[Complex32]) -> Vec<(f32, f32)> {
         let resolution = self.get_fft_frequency_resolution(array.len());
         array
             .iter()
@@ -205,34 +333,38 @@ impl SignalProcessor {
             .collect()
     }
 
-    pub fn cfar(db_fft_array: &Vec<f32>, gap: usize, refrence: usize, bias: f32) -> Vec<f32> {
+    /// Cell averaging CFAR threshold of a dB spectrum, the mean of `refrence` cells either
+    /// side of every bin, skipping `gap` guard cells, raised by `bias` dB.
+    ///
+    /// The offset is added rather than multiplied, a scaled mean would move the threshold
+    /// below the spectrum once the dB values turn negative.
+    ///
+    /// The threshold is in dB as well, a bin counts as detected when it is above it. Bins
+    /// near the edges average over the reference cells that exist.
+    pub fn cfar(db_fft_array: &[f32], gap: usize, refrence: usize, bias: f32) -> Vec<f32> {
         let mut ret_vec = vec![0.0; db_fft_array.len()];
-        for i in 0..db_fft_array.len() {
+        for (i, ret) in ret_vec.iter_mut().enumerate() {
             // first refrence
             let mut sum: f32 = 0.0;
             let mut len = 0;
 
             for j in (i as i32 - gap as i32 - refrence as i32)..(i as i32 - gap as i32) {
-                if j > 0 {
+                if j >= 0 {
                     sum += db_fft_array[j as usize];
                     len += 1;
                 }
             }
 
-            for j in (i + gap)..(i + gap + refrence) {
+            for j in (i + gap + 1)..(i + gap + 1 + refrence) {
                 if j < db_fft_array.len() {
                     sum += db_fft_array[j];
                     len += 1;
                 }
             }
 
-            let len = if len == 0 { refrence } else { len };
-
-            let avg = sum / (2.0 * len as f32);
-
-            let biased = bias * avg;
+            let avg = if len == 0 { 0.0 } else { sum / len as f32 };
 
-            ret_vec[i] = biased;
+            *ret = avg + bias;
         }
 
         ret_vec
@@ -270,3 +402,58 @@ impl SignalProcessor {
         1.0f32 / self.samples_rate as f32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation_needs_three_points() {
+        let processor = SignalProcessor::new(48000);
+        for len in 0..3 {
+            let magnetude = vec![(0.0, 1.0); len];
+            for index in 0..3 {
+                assert!(processor.interpolate_peak_at(&magnetude, index).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn interpolates_between_samples() {
+        let processor = SignalProcessor::new(48000);
+        // parabola with its vertex a quarter sample right of index 2
+        let magnetude: Vec<(f32, f32)> = (0..5)
+            .map(|i| (i as f32, 1.0 - (i as f32 - 2.25).powi(2)))
+            .collect();
+
+        let (time, value) = processor.interpolate_peak_at(&magnetude, 2).unwrap();
+        assert!((time - 2.25).abs() < 1e-5, "{time}");
+        assert!((value - 1.0).abs() < 1e-5, "{value}");
+    }
+
+    #[test]
+    fn cfar_threshold_sits_bias_db_above_the_reference_cells() {
+        // flat -40 dB floor with a single 0 dB line at bin 10
+        let mut spectrum = vec![-40.0; 21];
+        spectrum[10] = 0.0;
+
+        let threshold = SignalProcessor::cfar(&spectrum, 1, 2, 3.0);
+        assert_eq!(threshold.len(), spectrum.len());
+
+        // the guard cell keeps the line out of its own reference
+        assert_eq!(threshold[10], -37.0);
+        assert!(spectrum[10] > threshold[10]);
+        // bins two and three away see the line in one of their four reference cells
+        for i in [7, 8, 12, 13] {
+            assert_eq!(threshold[i], -27.0, "{i}");
+        }
+        // the edges only average the cells on one side
+        assert_eq!(threshold[0], -37.0);
+        assert_eq!(threshold[20], -37.0);
+
+        let detected: Vec<usize> = (0..spectrum.len())
+            .filter(|&i| spectrum[i] > threshold[i])
+            .collect();
+        assert_eq!(detected, [10]);
+    }
+}
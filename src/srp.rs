@@ -0,0 +1,317 @@
+use crate::SPEED_SOUND;
+use crate::gcc::TdoaMatrix;
+use crate::geometry::ArrayGeometry;
+use std::f32::consts::PI;
+
+/// Direction of arrival in radians. Azimuth is measured in the x-y plane from the x axis
+/// towards y, elevation from the x-y plane towards z.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Direction {
+    pub azimuth: f32,
+    pub elevation: f32,
+}
+
+impl Direction {
+    fn unit_vector(&self) -> [f64; 3] {
+        let (azimuth, elevation) = (self.azimuth as f64, self.elevation as f64);
+        [
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        ]
+    }
+}
+
+/// Steered response power over the scanned grid.
+pub struct SrpMap {
+    pub azimuths: Vec<f32>,
+    pub elevations: Vec<f32>,
+    /// `power[e][a]` is the power steered at `elevations[e]` and `azimuths[a]`.
+    pub power: Vec<Vec<f32>>,
+    pub best: Direction,
+    pub best_power: f32,
+}
+
+/// Steered response power localizer over every microphone pair of the array.
+///
+/// Instead of steering the cross spectra again for every direction, the power of a direction
+/// is the sum of the pairwise GCC correlations at the delays that direction would cause,
+/// which is the same quantity and reuses the correlations [`PairwiseGcc`] already computed.
+/// With PHAT weighting this is SRP-PHAT.
+///
+/// [`PairwiseGcc`]: crate::gcc::PairwiseGcc
+pub struct SrpPhat {
+    azimuths: Vec<f32>,
+    elevations: Vec<f32>,
+    pairs: Vec<(usize, usize)>,
+    // delays[e][a][p] is the expected delay of pairs[p] for elevations[e], azimuths[a]
+    delays: Vec<Vec<Vec<f32>>>,
+}
+
+impl SrpPhat {
+    /// Scans `azimuth_steps` directions around the full circle and `elevation_steps`
+    /// elevations from the horizon to straight up, a single elevation step scans the
+    /// horizon only. Planar arrays can't tell up from down, so negative elevations are
+    /// never scanned.
+    pub fn new(geometry: &ArrayGeometry, azimuth_steps: usize, elevation_steps: usize) -> Self {
+        let azimuth_steps = azimuth_steps.max(1);
+        let elevation_steps = elevation_steps.max(1);
+
+        let azimuths: Vec<f32> = (0..azimuth_steps)
+            .map(|i| 2.0 * PI * i as f32 / azimuth_steps as f32 - PI)
+            .collect();
+        let elevations: Vec<f32> = if elevation_steps == 1 {
+            vec![0.0]
+        } else {
+            (0..elevation_steps)
+                .map(|i| 0.5 * PI * i as f32 / (elevation_steps - 1) as f32)
+                .collect()
+        };
+
+        let microphones = geometry.microphones();
+        let mut pairs = Vec::new();
+        for a in microphones {
+            for b in microphones {
+                if a.channel < b.channel {
+                    pairs.push((a.channel, b.channel));
+                }
+            }
+        }
+
+        let delays = elevations
+            .iter()
+            .map(|elevation| {
+                azimuths
+                    .iter()
+                    .map(|azimuth| {
+                        let direction = Direction {
+                            azimuth: *azimuth,
+                            elevation: *elevation,
+                        };
+                        pairs
+                            .iter()
+                            .map(|(i, j)| expected_delay(geometry, *i, *j, &direction))
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        SrpPhat {
+            azimuths,
+            elevations,
+            pairs,
+            delays,
+        }
+    }
+
+    /// Power map and best direction for one frame, `None` if no pair has a correlation.
+    pub fn scan(&self, matrix: &TdoaMatrix) -> Option<SrpMap> {
+        let correlations: Vec<Option<&[(f32, f32)]>> = self
+            .pairs
+            .iter()
+            .map(|(i, j)| matrix.get(*i, *j).map(|x| x.correlation.as_slice()))
+            .collect();
+
+        if correlations.iter().all(|x| x.is_none()) {
+            return None;
+        }
+
+        let power: Vec<Vec<f32>> = self
+            .delays
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|delays| {
+                        delays
+                            .iter()
+                            .zip(correlations.iter())
+                            .filter_map(|(delay, correlation)| {
+                                correlation.map(|x| correlation_at(x, *delay))
+                            })
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let (best_elevation, best_azimuth, best_power) = power
+            .iter()
+            .enumerate()
+            .flat_map(|(e, row)| row.iter().enumerate().map(move |(a, p)| (e, a, *p)))
+            .max_by(|(_, _, x), (_, _, y)| x.total_cmp(y))?;
+
+        Some(SrpMap {
+            azimuths: self.azimuths.clone(),
+            elevations: self.elevations.clone(),
+            best: Direction {
+                azimuth: self.azimuths[best_azimuth],
+                elevation: self.elevations[best_elevation],
+            },
+            best_power,
+            power,
+        })
+    }
+}
+
+/// Seconds channel `i` hears a far field source in `direction` after channel `j`.
+pub fn expected_delay(geometry: &ArrayGeometry, i: usize, j: usize, direction: &Direction) -> f32 {
+    let (Some(a), Some(b)) = (geometry.microphone(i), geometry.microphone(j)) else {
+        return 0.0;
+    };
+
+    let u = direction.unit_vector();
+    let projection: f64 = (0..3).map(|k| (b.position[k] - a.position[k]) * u[k]).sum();
+
+    (projection / SPEED_SOUND) as f32
+}
+
+/// Linear interpolation of a uniformly spaced `(lag, value)` correlation.
+fn correlation_at(correlation: &[(f32, f32)], lag: f32) -> f32 {
+    if correlation.len() < 2 {
+        return 0.0;
+    }
+
+    let (t0, _) = correlation[0];
+    let step = correlation[1].0 - t0;
+    let position = (lag - t0) / step;
+
+    if position < 0.0 || position > (correlation.len() - 1) as f32 {
+        return 0.0;
+    }
+
+    let index = (position.floor() as usize).min(correlation.len() - 2);
+    let fraction = position - index as f32;
+
+    correlation[index].1 * (1.0 - fraction) + correlation[index + 1].1 * fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioSource;
+    use crate::frame::{Framer, Window};
+    use crate::gcc::PairwiseGcc;
+    use crate::generator::{SignalGenerator, SourceKind};
+    use crate::geometry::Microphone;
+    use crate::signal::SignalProcessor;
+    use rustfft::num_complex::Complex32;
+
+    const RATE: u32 = 48000;
+    const LEN: usize = 1024;
+
+    fn square(side: f64) -> ArrayGeometry {
+        let half = side / 2.0;
+        ArrayGeometry::new(
+            [(half, half), (-half, half), (-half, -half), (half, -half)]
+                .into_iter()
+                .enumerate()
+                .map(|(channel, (x, y))| Microphone {
+                    channel,
+                    position: [x, y, 0.0],
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    /// One frame of broadband noise arriving as a plane wave from `direction`, each channel
+    /// circularly delayed by its distance to the wavefront.
+    fn plane_wave(geometry: &ArrayGeometry, direction: &Direction) -> Vec<Vec<Complex32>> {
+        let mut state: u64 = 0x5EED;
+        let phases: Vec<f32> = (0..LEN / 2)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                2.0 * PI * (state >> 40) as f32 / (1u64 << 24) as f32
+            })
+            .collect();
+
+        let u = direction.unit_vector();
+        (0..geometry.required_channels())
+            .map(|channel| {
+                let position = geometry.microphone(channel).unwrap().position;
+                let projection: f64 = (0..3).map(|k| position[k] * u[k]).sum();
+                let delay = (-projection / SPEED_SOUND) as f32;
+
+                let mut spectrum = vec![Complex32::new(0.0, 0.0); LEN];
+                for k in 1..LEN / 2 {
+                    let phase = phases[k] - 2.0 * PI * k as f32 * RATE as f32 * delay / LEN as f32;
+                    spectrum[k] = Complex32::from_polar(1.0, phase);
+                    spectrum[LEN - k] = spectrum[k].conj();
+                }
+                spectrum
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plane_waves_peak_at_their_azimuth() {
+        let geometry = square(0.2);
+        let srp = SrpPhat::new(&geometry, 72, 1);
+        let mut estimator = PairwiseGcc::new(RATE, &geometry);
+
+        for degrees in [40.0f32, -100.0, 165.0] {
+            let direction = Direction {
+                azimuth: degrees.to_radians(),
+                elevation: 0.0,
+            };
+            let matrix = estimator.estimate_from_spectra(&plane_wave(&geometry, &direction), None);
+            let map = srp.scan(&matrix).unwrap();
+
+            assert!(
+                (map.best.azimuth - direction.azimuth).abs() < 1e-4,
+                "{degrees} found {}",
+                map.best.azimuth.to_degrees()
+            );
+            assert_eq!(map.power.len(), 1);
+            assert_eq!(map.power[0].len(), 72);
+        }
+    }
+
+    #[test]
+    fn expected_delay_has_the_sign_of_the_measured_delay() {
+        // the generator delays channel 0, the left end of the bar, so the source is on the
+        // +x side, 60 degrees off the bar axis
+        let geometry = ArrayGeometry::two_mic_bar(0.2).unwrap();
+        let delay = (0.2 * 60f64.to_radians().cos() / SPEED_SOUND) as f32;
+        let direction = Direction {
+            azimuth: 60f32.to_radians(),
+            elevation: 0.0,
+        };
+
+        let expected = expected_delay(&geometry, 0, 1, &direction);
+        assert!((expected - delay).abs() < 1e-7, "{expected} vs {delay}");
+        assert_eq!(expected_delay(&geometry, 1, 0, &direction), -expected);
+
+        let mut generator = SignalGenerator::new(RATE, LEN, SourceKind::WhiteNoise, delay).unwrap();
+        let mut processor = SignalProcessor::new(RATE);
+        let mut framers = [
+            Framer::new(LEN, LEN, Window::Hann).unwrap(),
+            Framer::new(LEN, LEN, Window::Hann).unwrap(),
+        ];
+        let mut estimator = PairwiseGcc::new(RATE, &geometry);
+        let srp = SrpPhat::new(&geometry, 72, 1);
+
+        for _ in 0..4 {
+            let block = generator.next_block().unwrap();
+            let spectra: Vec<Vec<Complex32>> = framers
+                .iter_mut()
+                .zip(&block)
+                .map(|(framer, samples)| processor.fft(&mut framer.push(samples).remove(0)))
+                .collect();
+
+            let matrix = estimator.estimate_from_spectra(&spectra, None);
+            let measured = matrix.delay(0, 1).unwrap();
+            assert!(
+                (measured - expected).abs() < 5e-6,
+                "{measured} vs {expected}"
+            );
+
+            // a bar can't tell the two sides of its axis apart
+            let best = srp.scan(&matrix).unwrap().best.azimuth.to_degrees();
+            assert!((best.abs() - 60.0).abs() <= 5.0, "{best}");
+        }
+    }
+}
@@ -0,0 +1,799 @@
+use crate::angle_wrap_f32;
+use crate::error::{Error, Result};
+use std::collections::VecDeque;
+use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_4, PI};
+
+/// Constant velocity Kalman filter following one direction estimate frame by frame.
+///
+/// The state is the angle and its rate of change in radians and radians per second. Every
+/// measurement is weighted by its confidence, a confidence of `1.0` has the standard deviation
+/// given as `measurement_noise` and lower confidences proportionally more. Measurements further
+/// than `gate` standard deviations of the innovation from the prediction are rejected as
+/// outliers. After `restart_after` rejections in a row that agree with each other, or
+/// `max_misses` rejections of any kind, the track restarts at the next one so a talker that
+/// really moved is picked up again.
+///
+/// A track starts with an angle standard deviation of `initial_std`, by default wide enough
+/// that an outlier it happens to start at is corrected by the next measurements instead of
+/// gating them out.
+pub struct AngleTracker {
+    process_noise: f32,
+    measurement_noise: f32,
+    gate: f32,
+    max_misses: usize,
+    restart_after: usize,
+    initial_std: f32,
+    misses: usize,
+    // angles rejected since the last accepted measurement
+    rejected: Vec<f32>,
+    // angle, rate and their covariance, `None` until the first measurement
+    state: Option<([f32; 2], [[f32; 2]; 2])>,
+}
+
+impl AngleTracker {
+    /// `process_noise` is the spectral density of the angular acceleration in rad²/s³,
+    /// `measurement_noise` the standard deviation of a fully confident measurement in radians.
+    pub fn new(process_noise: f32, measurement_noise: f32) -> Self {
+        AngleTracker {
+            process_noise,
+            measurement_noise,
+            gate: 3.0,
+            max_misses: 10,
+            restart_after: 3,
+            initial_std: FRAC_PI_4,
+            misses: 0,
+            rejected: Vec::new(),
+            state: None,
+        }
+    }
+
+    pub fn set_gate(&mut self, gate: f32) {
+        self.gate = gate;
+    }
+
+    pub fn set_max_misses(&mut self, max_misses: usize) {
+        self.max_misses = max_misses;
+    }
+
+    /// Rejected measurements in a row, all within the gate of each other, that restart the
+    /// track. `0` only restarts after `max_misses`.
+    pub fn set_restart_after(&mut self, restart_after: usize) {
+        self.restart_after = restart_after;
+    }
+
+    /// Standard deviation of the angle a track starts with in radians, never below that of
+    /// the measurement it starts at.
+    pub fn set_initial_std(&mut self, initial_std: f32) {
+        self.initial_std = initial_std;
+    }
+
+    /// Filtered angle in radians, `None` before the first measurement.
+    pub fn angle(&self) -> Option<f32> {
+        self.state.map(|(x, _)| x[0])
+    }
+
+    /// Filtered angular rate in radians per second.
+    pub fn rate(&self) -> Option<f32> {
+        self.state.map(|(x, _)| x[1])
+    }
+
+    pub fn reset(&mut self) {
+        self.state = None;
+        self.misses = 0;
+        self.rejected.clear();
+    }
+
+    /// Zeroes the rate and gives it the uncertainty of a new track, keeping the angle. For a
+    /// track whose talker paused, the rate before the pause says nothing about after it.
+    pub fn reset_rate(&mut self) {
+        if let Some((x, p)) = &mut self.state {
+            x[1] = 0.0;
+            p[0][1] = 0.0;
+            p[1][0] = 0.0;
+            p[1][1] = 1.0;
+        }
+    }
+
+    /// Advances the state by `dt` seconds, call it once per frame whether or not a
+    /// measurement follows.
+    pub fn predict(&mut self, dt: f32) {
+        let Some((x, p)) = &mut self.state else {
+            return;
+        };
+
+        x[0] = angle_wrap_f32(x[0] + dt * x[1]);
+
+        // P = F P F' + Q with F = [[1, dt], [0, 1]]
+        let p00 = p[0][0] + dt * (p[1][0] + p[0][1]) + dt * dt * p[1][1];
+        let p01 = p[0][1] + dt * p[1][1];
+        let p11 = p[1][1];
+
+        let q = self.process_noise;
+        p[0][0] = p00 + q * dt * dt * dt / 3.0;
+        p[0][1] = p01 + q * dt * dt / 2.0;
+        p[1][0] = p[0][1];
+        p[1][1] = p11 + q * dt;
+    }
+
+    /// Squared innovation of a measurement over its predicted variance, the quantity the
+    /// gate is compared against. `None` before the first measurement.
+    pub fn distance(&self, angle: f32, confidence: f32) -> Option<f32> {
+        let (x, p) = self.state.as_ref()?;
+        let innovation = angle_wrap_f32(angle - x[0]);
+        Some(innovation * innovation / (p[0][0] + self.measurement_variance(confidence)))
+    }
+
+    /// Corrects the state with a measured `angle` and its `confidence` in `0.0..=1.0`.
+    ///
+    /// Returns `false` if the measurement was gated out as an outlier.
+    pub fn update(&mut self, angle: f32, confidence: f32) -> bool {
+        let r = self.measurement_variance(confidence);
+
+        let Some((x, p)) = &mut self.state else {
+            self.start(angle, r);
+            return true;
+        };
+
+        let innovation = angle_wrap_f32(angle - x[0]);
+        let s = p[0][0] + r;
+
+        if innovation * innovation > self.gate * self.gate * s {
+            self.misses += 1;
+            self.rejected.push(angle);
+
+            // the rejected angles agree when they are within the gate of the latest one
+            let agreeing = self.restart_after > 0
+                && self.rejected.len() >= self.restart_after
+                && self
+                    .rejected
+                    .iter()
+                    .rev()
+                    .take(self.restart_after)
+                    .all(|x| {
+                        let spread = angle_wrap_f32(x - angle);
+                        spread * spread <= self.gate * self.gate * 2.0 * r
+                    });
+
+            if agreeing || self.misses > self.max_misses {
+                self.start(angle, r);
+                return true;
+            }
+            return false;
+        }
+
+        self.misses = 0;
+        self.rejected.clear();
+
+        let k = [p[0][0] / s, p[1][0] / s];
+        x[0] = angle_wrap_f32(x[0] + k[0] * innovation);
+        x[1] += k[1] * innovation;
+
+        // P = (I - K H) P
+        let (p00, p01, p11) = (p[0][0], p[0][1], p[1][1]);
+        p[0][0] = (1.0 - k[0]) * p00;
+        p[0][1] = (1.0 - k[0]) * p01;
+        p[1][0] = p[0][1];
+        p[1][1] = p11 - k[1] * p01;
+
+        true
+    }
+
+    fn measurement_variance(&self, confidence: f32) -> f32 {
+        let sigma = self.measurement_noise / confidence.clamp(0.05, 1.0);
+        sigma * sigma
+    }
+
+    fn start(&mut self, angle: f32, r: f32) {
+        // the rate is unknown, a radian per second covers a walking talker nearby
+        let variance = r.max(self.initial_std * self.initial_std);
+        self.state = Some(([angle, 0.0], [[variance, 0.0], [0.0, 1.0]]));
+        self.misses = 0;
+        self.rejected.clear();
+    }
+}
+
+/// One talker followed by [`MultiTargetTracker`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Track {
+    /// Stays the same for the lifetime of the track, never reused.
+    pub id: u32,
+    pub angle: f32,
+    pub rate: f32,
+    /// Confidence of the detection the track was last updated with.
+    pub confidence: f32,
+    /// Index of the detection the latest [`MultiTargetTracker::update`] associated to the
+    /// track, `None` if it had none.
+    pub detection: Option<usize>,
+}
+
+struct TrackState {
+    id: u32,
+    filter: AngleTracker,
+    // whether each update since the birth had a detection, until confirmed
+    hits: VecDeque<bool>,
+    misses: usize,
+    // seconds since the last detection, speech or not
+    since_hit: f32,
+    confirmed: bool,
+    confidence: f32,
+    detection: Option<usize>,
+}
+
+/// Follows several talkers at once from per-frame angle detections.
+///
+/// Detections are associated to tracks by global nearest neighbour, the closest gated
+/// track and detection pair first. A detection no track claims starts a tentative track,
+/// which is confirmed after `m` hits in its first `n` updates and dropped if it can't reach
+/// them anymore. Confirmed tracks are deleted after `max_misses` updates in a row without a
+/// detection, and any track after `max_coast` seconds without one, so a talker after a long
+/// pause doesn't inherit the id of the previous one.
+pub struct MultiTargetTracker {
+    process_noise: f32,
+    measurement_noise: f32,
+    gate: f32,
+    birth: (usize, usize),
+    max_misses: usize,
+    max_coast: f32,
+    // whether `update` ran since the last `predict`
+    updated: bool,
+    next_id: u32,
+    tracks: Vec<TrackState>,
+}
+
+impl MultiTargetTracker {
+    /// Noise parameters of every track, as for [`AngleTracker::new`].
+    pub fn new(process_noise: f32, measurement_noise: f32) -> Self {
+        MultiTargetTracker {
+            process_noise,
+            measurement_noise,
+            gate: 3.0,
+            birth: (3, 5),
+            max_misses: 30,
+            max_coast: 2.0,
+            updated: false,
+            next_id: 0,
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Association gate in standard deviations of the innovation.
+    pub fn set_gate(&mut self, gate: f32) {
+        self.gate = gate;
+    }
+
+    /// A track is confirmed after `m` hits in its first `n` updates.
+    pub fn set_birth(&mut self, m: usize, n: usize) -> Result<()> {
+        if m < 1 || m > n {
+            return Err(Error::Config(format!(
+                "Track birth needs 1 <= m <= n, found {m} of {n}"
+            )));
+        }
+
+        self.birth = (m, n);
+        Ok(())
+    }
+
+    pub fn set_max_misses(&mut self, max_misses: usize) {
+        self.max_misses = max_misses;
+    }
+
+    /// Seconds a track survives without a detection, whether or not anyone talks meanwhile.
+    pub fn set_max_coast(&mut self, max_coast: f32) {
+        self.max_coast = max_coast;
+    }
+
+    /// Confirmed tracks, oldest first.
+    pub fn tracks(&self) -> Vec<Track> {
+        self.tracks
+            .iter()
+            .filter(|x| x.confirmed)
+            .filter_map(|x| {
+                Some(Track {
+                    id: x.id,
+                    angle: x.filter.angle()?,
+                    rate: x.filter.rate()?,
+                    confidence: x.confidence,
+                    detection: x.detection,
+                })
+            })
+            .collect()
+    }
+
+    /// Advances every track by `dt` seconds, call it once per frame.
+    ///
+    /// Frames without an [`Self::update`] don't count as misses, so tracks survive short
+    /// pauses in speech. Through a pause a track holds its angle and uncertainty with the
+    /// rate reset, and it is deleted once `max_coast` seconds passed since its last detection.
+    pub fn predict(&mut self, dt: f32) {
+        let paused = !std::mem::replace(&mut self.updated, false);
+
+        for track in self.tracks.iter_mut() {
+            track.since_hit += dt;
+
+            if paused {
+                track.filter.reset_rate();
+            } else {
+                track.filter.predict(dt);
+            }
+        }
+
+        let max_coast = self.max_coast;
+        self.tracks.retain(|track| track.since_hit <= max_coast);
+    }
+
+    /// Associates one frame of `(angle, confidence)` detections and returns the confirmed
+    /// tracks.
+    pub fn update(&mut self, detections: &[(f32, f32)]) -> Vec<Track> {
+        self.updated = true;
+
+        let mut pairs: Vec<(usize, usize, f32)> = self
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(t, track)| {
+                detections
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(d, (angle, confidence))| {
+                        Some((t, d, track.filter.distance(*angle, *confidence)?))
+                    })
+            })
+            .filter(|(_, _, distance)| *distance <= self.gate * self.gate)
+            .collect();
+        pairs.sort_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+
+        let mut track_hit = vec![false; self.tracks.len()];
+        let mut detection_used = vec![false; detections.len()];
+
+        for track in self.tracks.iter_mut() {
+            track.detection = None;
+        }
+
+        for (t, d, _) in pairs {
+            if track_hit[t] || detection_used[d] {
+                continue;
+            }
+
+            let (angle, confidence) = detections[d];
+            let track = &mut self.tracks[t];
+            track.filter.update(angle, confidence);
+            track.confidence = confidence;
+            track.detection = Some(d);
+
+            track_hit[t] = true;
+            detection_used[d] = true;
+        }
+
+        let (m, n) = self.birth;
+        let max_misses = self.max_misses;
+
+        for (track, hit) in self.tracks.iter_mut().zip(track_hit) {
+            track.misses = if hit { 0 } else { track.misses + 1 };
+            if hit {
+                track.since_hit = 0.0;
+            }
+
+            if !track.confirmed {
+                track.hits.push_back(hit);
+                let count = track.hits.iter().filter(|x| **x).count();
+                if count >= m {
+                    track.confirmed = true;
+                }
+            }
+        }
+
+        self.tracks.retain(|track| {
+            if track.confirmed {
+                track.misses <= max_misses
+            } else {
+                // the hits still possible in the remaining frames have to reach `m`
+                let count = track.hits.iter().filter(|x| **x).count();
+                count + n.saturating_sub(track.hits.len()) >= m
+            }
+        });
+
+        for (d, (angle, confidence)) in detections.iter().enumerate() {
+            if detection_used[d] {
+                continue;
+            }
+
+            let mut filter = AngleTracker::new(self.process_noise, self.measurement_noise);
+            filter.set_gate(self.gate);
+            // the birth rule vets new tracks here, a wide start would let them claim the
+            // detections of other talkers
+            filter.set_initial_std(0.0);
+            filter.update(*angle, *confidence);
+
+            self.tracks.push(TrackState {
+                id: self.next_id,
+                filter,
+                hits: VecDeque::from([true]),
+                misses: 0,
+                since_hit: 0.0,
+                confirmed: m <= 1,
+                confidence: *confidence,
+                detection: Some(d),
+            });
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+
+        self.tracks()
+    }
+}
+
+/// Causal second order Butterworth low-pass smoothing a stream of direction estimates.
+///
+/// The filter is designed for the rate the estimates arrive at, not the audio rate, so the
+/// cutoff keeps its meaning in Hz of angle change whatever the frame hop or sample rate. It
+/// starts at the first input instead of ramping up from zero.
+#[derive(Clone)]
+pub struct PostFilter {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+    primed: bool,
+}
+
+impl PostFilter {
+    /// `estimate_rate` is the number of estimates per second, see [`Self::estimate_rate`],
+    /// and `cutoff` has to be below half of it.
+    pub fn new(estimate_rate: f32, cutoff: f32) -> Result<Self> {
+        if !(cutoff > 0.0 && cutoff < estimate_rate / 2.0) {
+            return Err(Error::Config(format!(
+                "Post filter cutoff of {cutoff} Hz must be between 0 and {} Hz",
+                estimate_rate / 2.0
+            )));
+        }
+
+        let w0 = 2.0 * PI * cutoff / estimate_rate;
+        let alpha = w0.sin() / (2.0 * FRAC_1_SQRT_2);
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - w0.cos()) / a0;
+
+        Ok(PostFilter {
+            b: [b1 / 2.0, b1, b1 / 2.0],
+            a: [-2.0 * w0.cos() / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+            primed: false,
+        })
+    }
+
+    /// Estimates per second of a pipeline producing one estimate every `hop` samples.
+    pub fn estimate_rate(samples_per_sec: u32, hop: usize) -> f32 {
+        samples_per_sec as f32 / hop as f32
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        if !self.primed {
+            self.x = [input; 2];
+            self.y = [input; 2];
+            self.primed = true;
+        }
+
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.primed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the frame interval of a 1024 point frame with a 512 sample hop at 48 kHz
+    const DT: f32 = 512.0 / 48000.0;
+
+    #[test]
+    fn converges_to_a_constant_angle() {
+        let mut tracker = AngleTracker::new(2.0, 0.05);
+        assert_eq!(tracker.angle(), None);
+
+        // alternating measurement noise of a standard deviation around the true angle
+        for i in 0..200 {
+            tracker.predict(DT);
+            let noise = if i % 2 == 0 { 0.05 } else { -0.05 };
+            assert!(tracker.update(0.6 + noise, 1.0));
+        }
+
+        let angle = tracker.angle().unwrap();
+        assert!((angle - 0.6).abs() < 0.02, "{angle}");
+        assert!(tracker.rate().unwrap().abs() < 0.5, "{:?}", tracker.rate());
+    }
+
+    #[test]
+    fn rejects_a_single_outlier() {
+        let mut tracker = AngleTracker::new(2.0, 0.05);
+        for _ in 0..50 {
+            tracker.predict(DT);
+            tracker.update(0.6, 1.0);
+        }
+
+        tracker.predict(DT);
+        assert!(!tracker.update(-1.5, 1.0));
+        assert!((tracker.angle().unwrap() - 0.6).abs() < 0.01);
+
+        // the next measurement on the track is accepted again
+        tracker.predict(DT);
+        assert!(tracker.update(0.6, 1.0));
+        assert!((tracker.angle().unwrap() - 0.6).abs() < 0.01);
+    }
+
+    #[test]
+    fn restarts_after_agreeing_rejections() {
+        let mut tracker = AngleTracker::new(2.0, 0.05);
+        tracker.set_restart_after(3);
+        for _ in 0..50 {
+            tracker.predict(DT);
+            tracker.update(0.6, 1.0);
+        }
+
+        // the talker moved, the first two measurements at the new place are outliers
+        for _ in 0..2 {
+            tracker.predict(DT);
+            assert!(!tracker.update(-1.5, 1.0));
+            assert!((tracker.angle().unwrap() - 0.6).abs() < 0.01);
+        }
+
+        tracker.predict(DT);
+        assert!(tracker.update(-1.5, 1.0));
+        assert_eq!(tracker.angle(), Some(-1.5));
+        assert_eq!(tracker.rate(), Some(0.0));
+    }
+
+    #[test]
+    fn scattered_rejections_only_restart_after_max_misses() {
+        let mut tracker = AngleTracker::new(2.0, 0.05);
+        tracker.set_restart_after(3);
+        tracker.set_max_misses(4);
+        for _ in 0..50 {
+            tracker.predict(DT);
+            tracker.update(0.6, 1.0);
+        }
+
+        // outliers that don't agree with each other
+        for angle in [-1.5, 2.5, -0.5, 1.8] {
+            tracker.predict(DT);
+            assert!(!tracker.update(angle, 1.0), "{angle}");
+        }
+
+        tracker.predict(DT);
+        assert!(tracker.update(-2.5, 1.0));
+        assert_eq!(tracker.angle(), Some(-2.5));
+    }
+
+    #[test]
+    fn estimates_the_rate_of_a_sweep() {
+        let mut tracker = AngleTracker::new(2.0, 0.02);
+        let rate = 0.5;
+
+        for i in 0..500 {
+            tracker.predict(DT);
+            tracker.update(-1.0 + rate * DT * i as f32, 1.0);
+        }
+
+        let estimated = tracker.rate().unwrap();
+        assert!((estimated - rate).abs() < 0.05, "{estimated}");
+        let expected = -1.0 + rate * DT * 499.0;
+        assert!((tracker.angle().unwrap() - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn follows_a_sweep_across_the_wrap() {
+        let mut tracker = AngleTracker::new(2.0, 0.02);
+        let rate = 1.0;
+
+        for i in 0..300 {
+            tracker.predict(DT);
+            let angle = angle_wrap_f32(3.0 + rate * DT * i as f32);
+            assert!(tracker.update(angle, 1.0), "{i}");
+        }
+
+        assert!((tracker.rate().unwrap() - rate).abs() < 0.1);
+    }
+
+    fn talkers() -> MultiTargetTracker {
+        let mut talkers = MultiTargetTracker::new(2.0, 0.05);
+        talkers.set_birth(3, 5).unwrap();
+        talkers.set_max_misses(30);
+        talkers
+    }
+
+    /// Runs speech frames with the same `detections` until a track is confirmed.
+    fn confirm(talkers: &mut MultiTargetTracker, detections: &[(f32, f32)]) -> Vec<Track> {
+        for _ in 0..5 {
+            talkers.predict(DT);
+            let tracks = talkers.update(detections);
+            if tracks.len() == detections.len() {
+                return tracks;
+            }
+        }
+        panic!("no track confirmed");
+    }
+
+    #[test]
+    fn tracks_are_born_after_m_of_n_hits() {
+        let mut talkers = talkers();
+
+        // hit, miss, hit, miss, hit: the third hit in five updates confirms
+        for (frame, detections) in [vec![(0.8, 1.0)], vec![], vec![(0.8, 1.0)], vec![]]
+            .iter()
+            .enumerate()
+        {
+            talkers.predict(DT);
+            assert!(talkers.update(detections).is_empty(), "{frame}");
+        }
+        talkers.predict(DT);
+        let tracks = talkers.update(&[(0.8, 1.0)]);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].id, 0);
+
+        // two hits in the first five updates can't confirm, the tentative track is dropped
+        let mut talkers = MultiTargetTracker::new(2.0, 0.05);
+        for detections in [vec![(0.8, 1.0)], vec![(0.8, 1.0)], vec![], vec![], vec![]] {
+            talkers.predict(DT);
+            assert!(talkers.update(&detections).is_empty());
+        }
+        talkers.predict(DT);
+        talkers.update(&[(0.8, 1.0)]);
+        // the next detection starts over with a new id
+        assert_eq!(confirm(&mut talkers, &[(0.8, 1.0)])[0].id, 1);
+    }
+
+    #[test]
+    fn tracks_are_deleted_after_max_misses() {
+        let mut talkers = talkers();
+        talkers.set_max_misses(5);
+        confirm(&mut talkers, &[(0.8, 1.0)]);
+
+        for miss in 0..5 {
+            talkers.predict(DT);
+            assert_eq!(talkers.update(&[]).len(), 1, "{miss}");
+        }
+        talkers.predict(DT);
+        assert!(talkers.update(&[]).is_empty());
+    }
+
+    #[test]
+    fn two_talkers_keep_their_ids() {
+        let mut talkers = talkers();
+        let tracks = confirm(&mut talkers, &[(0.8, 1.0), (-0.9, 0.7)]);
+        let id_of = |tracks: &[Track], angle: f32| {
+            tracks
+                .iter()
+                .find(|x| (x.angle - angle).abs() < 0.2)
+                .map(|x| x.id)
+        };
+        let (first, second) = (id_of(&tracks, 0.8).unwrap(), id_of(&tracks, -0.9).unwrap());
+        assert_ne!(first, second);
+
+        // the detections swap order and drift, each stays with its nearest track
+        for i in 0..100 {
+            let drift = 0.002 * i as f32;
+            talkers.predict(DT);
+            let tracks = talkers.update(&[(-0.9 - drift, 0.7), (0.8 + drift, 1.0)]);
+
+            assert_eq!(tracks.len(), 2);
+            assert_eq!(id_of(&tracks, 0.8 + drift), Some(first), "{i}");
+            assert_eq!(id_of(&tracks, -0.9 - drift), Some(second), "{i}");
+
+            let track = tracks.iter().find(|x| x.id == second).unwrap();
+            assert_eq!(track.detection, Some(0));
+            assert_eq!(track.confidence, 0.7);
+        }
+    }
+
+    #[test]
+    fn no_id_handover_after_silence() {
+        let mut talkers = talkers();
+        assert_eq!(confirm(&mut talkers, &[(0.8, 1.0)])[0].id, 0);
+
+        // a minute of silence
+        for _ in 0..(60.0 / 0.0107) as usize {
+            talkers.predict(0.0107);
+        }
+        assert!(talkers.tracks().is_empty());
+
+        let tracks = confirm(&mut talkers, &[(-0.9, 1.0)]);
+        assert_eq!(tracks.len(), 1);
+        assert_ne!(tracks[0].id, 0);
+    }
+
+    #[test]
+    fn short_pauses_hold_the_track() {
+        let mut talkers = talkers();
+
+        // a talker walking at half a radian per second
+        for i in 0..100 {
+            talkers.predict(DT);
+            talkers.update(&[(0.5 * DT * i as f32, 1.0)]);
+        }
+        let before = talkers.tracks()[0];
+        assert!(before.rate > 0.3, "{}", before.rate);
+
+        // one second without speech neither moves the track on its old rate nor widens
+        // its gate to the whole circle
+        for _ in 0..(1.0 / DT) as usize {
+            talkers.predict(DT);
+        }
+        let held = talkers.tracks()[0];
+        assert!(
+            (held.angle - before.angle).abs() < 0.01,
+            "{held:?} {before:?}"
+        );
+        assert_eq!(held.rate, 0.0);
+
+        talkers.predict(DT);
+        let tracks = talkers.update(&[(before.angle + 0.05, 1.0), (before.angle - 1.5, 1.0)]);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].id, before.id);
+        assert_eq!(tracks[0].detection, Some(0));
+    }
+
+    /// Steady state amplitude of the post-filter for a sine of `frequency` Hz.
+    fn gain(estimate_rate: f32, cutoff: f32, frequency: f32) -> f32 {
+        let mut filter = PostFilter::new(estimate_rate, cutoff).unwrap();
+        let samples = (20.0 * estimate_rate / frequency.min(cutoff)) as usize;
+
+        (0..samples)
+            .map(|n| filter.process((2.0 * PI * frequency * n as f32 / estimate_rate).sin()))
+            .skip(samples / 2)
+            .fold(0.0, |peak: f32, x| peak.max(x.abs()))
+    }
+
+    #[test]
+    fn post_filter_passes_constant_angles_unchanged() {
+        let mut filter = PostFilter::new(PostFilter::estimate_rate(48000, 512), 2.0).unwrap();
+
+        // primed with the first input, so there is no ramp up from zero
+        for _ in 0..100 {
+            assert!((filter.process(1.2) - 1.2).abs() < 1e-5);
+        }
+
+        // and primed again after a reset
+        filter.reset();
+        assert!((filter.process(-0.4) + 0.4).abs() < 1e-5);
+
+        // a step settles on the new value
+        let last = (0..500).map(|_| filter.process(0.9)).last().unwrap();
+        assert!((last - 0.9).abs() < 1e-4, "{last}");
+    }
+
+    #[test]
+    fn post_filter_cutoff_follows_the_estimate_rate() {
+        let half_power = FRAC_1_SQRT_2;
+
+        for (samples_per_sec, hop) in [(48000, 512), (48000, 256), (16000, 160), (44100, 1024)] {
+            let rate = PostFilter::estimate_rate(samples_per_sec, hop);
+
+            let at_cutoff = gain(rate, 2.0, 2.0);
+            assert!(
+                (at_cutoff - half_power).abs() < 0.01,
+                "{samples_per_sec} {hop}: {at_cutoff}"
+            );
+            assert!(gain(rate, 2.0, 0.2) > 0.99);
+            assert!(gain(rate, 2.0, 10.0) < 0.1);
+        }
+    }
+
+    #[test]
+    fn post_filter_rejects_cutoffs_above_nyquist() {
+        let rate = PostFilter::estimate_rate(48000, 512);
+        assert!(matches!(PostFilter::new(rate, 0.0), Err(Error::Config(_))));
+        assert!(matches!(
+            PostFilter::new(rate, rate / 2.0),
+            Err(Error::Config(_))
+        ));
+        assert!(PostFilter::new(rate, rate / 2.0 - 1.0).is_ok());
+    }
+}
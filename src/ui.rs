@@ -3,60 +3,251 @@ use eframe::egui::Visuals;
 use egui_plotter::EguiBackend;
 use plotters::prelude::*;
 use std::collections::VecDeque;
-use std::sync::mpsc::Receiver;
+use std::error::Error;
+use std::sync::mpsc::{Receiver, SyncSender};
+use voice_direction_finder::audio::{DeviceInfo, DeviceSelector};
+use voice_direction_finder::error;
+use voice_direction_finder::gcc::{PeakQuality, Weighting};
+use voice_direction_finder::geometry::ArrayGeometry;
+use voice_direction_finder::music::MusicSpectrum;
+use voice_direction_finder::srp::SrpMap;
+
+/// Receiving ends of the plot channels fed by the processing thread.
+pub struct PlotReceivers {
+    pub right_rx: Receiver<Vec<(f32, f32)>>,
+    pub left_rx: Receiver<Vec<(f32, f32)>>,
+    pub right_cfar_rx: Receiver<Vec<(f32, f32)>>,
+    pub left_cfar_rx: Receiver<Vec<(f32, f32)>>,
+    pub phase_rx: Receiver<VecDeque<f32>>,
+    pub cross_correlation_rx: Receiver<Vec<(f32, f32)>>,
+    pub speech_rx: Receiver<bool>,
+    pub quality_rx: Receiver<(f32, PeakQuality)>,
+    pub srp_rx: Receiver<Option<SrpMap>>,
+    pub music_rx: Receiver<Option<MusicSpectrum>>,
+    /// Track id and angle history of every talker, NaN while that talker is quiet.
+    pub tracks_rx: Receiver<Vec<(u32, VecDeque<f32>)>>,
+}
+
+const TRACK_COLORS: [RGBColor; 4] = [
+    RGBColor(200, 120, 255),
+    RGBColor(80, 220, 220),
+    RGBColor(255, 120, 180),
+    RGBColor(220, 220, 220),
+];
+
+/// Input devices the UI offers, where a choice is sent and whether the processing thread
+/// switched to it.
+pub struct DevicePicker {
+    devices: Vec<DeviceInfo>,
+    // index of the open device, `None` for a WAV replay
+    selected: Option<usize>,
+    // index of the device the processing thread is switching to
+    pending: Option<usize>,
+    // why the last switch was refused
+    error: Option<String>,
+    device_tx: SyncSender<DeviceSelector>,
+    switched_rx: Receiver<error::Result<()>>,
+}
+
+impl DevicePicker {
+    /// `selected` is the index of the open device. Every selector sent on `device_tx` is
+    /// answered on `switched_rx`, the selection only changes once the switch succeeded.
+    pub fn new(
+        devices: Vec<DeviceInfo>,
+        selected: Option<usize>,
+        device_tx: SyncSender<DeviceSelector>,
+        switched_rx: Receiver<error::Result<()>>,
+    ) -> Self {
+        DevicePicker {
+            devices,
+            selected,
+            pending: None,
+            error: None,
+            device_tx,
+            switched_rx,
+        }
+    }
+}
 
 pub struct Application {
-    right_rx: Receiver<Vec<(f32, f32)>>,
-    left_rx: Receiver<Vec<(f32, f32)>>,
-    right_cfar_rx: Receiver<Vec<(f32, f32)>>,
-    left_cfar_rx: Receiver<Vec<(f32, f32)>>,
-    phase_rx: Receiver<VecDeque<f32>>,
-    cross_correlation_rx: Receiver<Vec<(f32, f32)>>,
+    plots: PlotReceivers,
+    weighting_tx: SyncSender<Weighting>,
+    weighting: Weighting,
+    devices: DevicePicker,
+    geometry: ArrayGeometry,
+    // channels whose delay the angle plots show
+    pair: (usize, usize),
 }
 
 impl Application {
     pub fn new(
         cc: &eframe::CreationContext<'_>,
-        right_rx: Receiver<Vec<(f32, f32)>>,
-        left_rx: Receiver<Vec<(f32, f32)>>,
-        right_cfar_rx: Receiver<Vec<(f32, f32)>>,
-        left_cfar_rx: Receiver<Vec<(f32, f32)>>,
-        phase_rx: Receiver<VecDeque<f32>>,
-        cross_correlation_rx: Receiver<Vec<(f32, f32)>>,
+        plots: PlotReceivers,
+        weighting_tx: SyncSender<Weighting>,
+        weighting: Weighting,
+        devices: DevicePicker,
+        geometry: ArrayGeometry,
+        pair: (usize, usize),
     ) -> Self {
         let context = &cc.egui_ctx;
         context.set_visuals(Visuals::dark());
 
         Application {
-            right_rx,
-            left_rx,
-            right_cfar_rx,
-            left_cfar_rx,
-            phase_rx,
-            cross_correlation_rx: cross_correlation_rx,
+            plots,
+            weighting_tx,
+            weighting,
+            devices,
+            geometry,
+            pair,
+        }
+    }
+
+    fn delay_to_angle(&self, delay: f32) -> f32 {
+        let (a, b) = self.pair;
+        self.geometry
+            .delay_to_angle(a, b, delay)
+            .unwrap_or(f32::NAN)
+    }
+
+    fn device_controls(&mut self, ui: &mut egui::Ui) {
+        let picker = &mut self.devices;
+
+        if let Ok(switched) = picker.switched_rx.try_recv() {
+            let pending = picker.pending.take();
+            match switched {
+                Ok(()) => {
+                    picker.selected = pending;
+                    picker.error = None;
+                }
+                Err(e) => picker.error = Some(e.to_string()),
+            }
+        }
+
+        let current = picker
+            .selected
+            .and_then(|i| picker.devices.get(i))
+            .map_or_else(|| String::from("WAV replay"), |x| x.name.clone());
+
+        egui::ComboBox::from_label("Input")
+            .selected_text(current)
+            .show_ui(ui, |ui| {
+                for device in &picker.devices {
+                    let selected = picker.selected == Some(device.index);
+                    if ui
+                        .selectable_label(selected, format!("[{}] {}", device.host, device.name))
+                        .clicked()
+                        && !selected
+                        && picker.pending.is_none()
+                        && picker
+                            .device_tx
+                            .try_send(DeviceSelector::Index(device.index))
+                            .is_ok()
+                    {
+                        picker.pending = Some(device.index);
+                    }
+                }
+            });
+
+        if picker.pending.is_some() {
+            ui.label("Switching...");
+        } else if let Some(error) = &picker.error {
+            ui.colored_label(egui::Color32::from_rgb(255, 120, 120), error);
+        }
+    }
+
+    fn weighting_controls(&mut self, ui: &mut egui::Ui) {
+        let previous = self.weighting;
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("GCC weighting")
+                .selected_text(self.weighting.to_string())
+                .show_ui(ui, |ui| {
+                    for weighting in Weighting::ALL {
+                        let selected = std::mem::discriminant(&self.weighting)
+                            == std::mem::discriminant(&weighting);
+                        if ui
+                            .selectable_label(selected, weighting.to_string())
+                            .clicked()
+                            && !selected
+                        {
+                            self.weighting = weighting;
+                        }
+                    }
+                });
+
+            if let Weighting::PhatBeta(beta) = &mut self.weighting {
+                ui.add(egui::Slider::new(beta, 0.0..=1.0).text("β"));
+            }
+        });
+
+        if self.weighting != previous {
+            let _ = self.weighting_tx.try_send(self.weighting);
         }
     }
 }
 
 impl eframe::App for Application {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
-        if let Ok(right) = self.right_rx.recv()
-            && let Ok(left) = self.left_rx.recv()
-            && let Ok(left_cfar) = self.left_cfar_rx.recv()
-            && let Ok(right_cfar) = self.right_cfar_rx.recv()
-            && let Ok(cross_correlation) = self.cross_correlation_rx.recv()
-            && let Ok(phases) = self.phase_rx.recv()
+        if let Ok(right) = self.plots.right_rx.recv()
+            && let Ok(left) = self.plots.left_rx.recv()
+            && let Ok(left_cfar) = self.plots.left_cfar_rx.recv()
+            && let Ok(right_cfar) = self.plots.right_cfar_rx.recv()
+            && let Ok(cross_correlation) = self.plots.cross_correlation_rx.recv()
+            && let Ok(phases) = self.plots.phase_rx.recv()
+            && let Ok(speech) = self.plots.speech_rx.recv()
+            && let Ok((confidence, quality)) = self.plots.quality_rx.recv()
+            && let Ok(power_map) = self.plots.srp_rx.recv()
+            && let Ok(pseudo_spectrum) = self.plots.music_rx.recv()
+            && let Ok(tracks) = self.plots.tracks_rx.recv()
+            && let Some(&(high, _)) = left.last()
+            && let Some(&(high_cross, _)) = cross_correlation.last()
+            && let Some(&(low_cross, _)) = cross_correlation.first()
+            && let Some(max_cross) = cross_correlation
+                .iter()
+                .map(|(_x, y)| *y)
+                .max_by(|a, b| a.total_cmp(b))
         {
             // self.add_element_in_queue(phases);
 
-            let (high, _) = left.last().unwrap();
-            let (high_cross, _) = cross_correlation.last().unwrap();
-            let (low_cross, _) = cross_correlation.get(0).unwrap();
-            let max_cross = cross_correlation
-                .iter()
-                .map(|(_x, y)| y)
-                .max_by(|a, b| a.total_cmp(b))
-                .unwrap();
+            egui::TopBottomPanel::top("controls").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    self.device_controls(ui);
+                    ui.separator();
+                    self.weighting_controls(ui);
+                    ui.separator();
+                    if speech {
+                        ui.colored_label(egui::Color32::from_rgb(148, 255, 139), "Speech");
+                    } else {
+                        ui.weak("Silence");
+                    }
+                    ui.separator();
+                    ui.label(format!(
+                        "confidence {:.2}   peak {:.3}   peak/2nd {:.2}   peak/mean {:.1}   sharpness {:.2}",
+                        confidence,
+                        quality.peak,
+                        quality.peak_to_second,
+                        quality.peak_to_mean,
+                        quality.sharpness
+                    ));
+                    if let Some(map) = &power_map {
+                        ui.separator();
+                        ui.label(format!(
+                            "SRP azimuth {:.0}°   elevation {:.0}°",
+                            map.best.azimuth.to_degrees(),
+                            map.best.elevation.to_degrees()
+                        ));
+                    }
+                    if let Some(spectrum) = &pseudo_spectrum {
+                        ui.separator();
+                        let peaks: Vec<String> = spectrum
+                            .peaks
+                            .iter()
+                            .map(|x| format!("{:.0}°", x.to_degrees()))
+                            .collect();
+                        ui.label(format!("MUSIC {}", peaks.join("  ")));
+                    }
+                });
+            });
 
             egui::CentralPanel::default().show(ctx, |ui| {
                 // Top panel for Left microphone
@@ -66,39 +257,36 @@ impl eframe::App for Application {
                         ui.add_space(3.0);
 
                         let root = EguiBackend::new(ui).into_drawing_area();
-                        root.fill(&RGBColor(35, 35, 40)).unwrap();
-
-                        let mut chart = ChartBuilder::on(&root)
-                            .margin(8)
-                            .x_label_area_size(35)
-                            .y_label_area_size(45)
-                            .build_cartesian_2d(0.0f32..*high, 0f32..60f32)
-                            .unwrap();
-
-                        chart
-                            .configure_mesh()
-                            .x_desc("Frequency (Hz)")
-                            .y_desc("Magnitude (dB)")
-                            .label_style(("sans-serif", 13, &WHITE))
-                            .axis_style(&RGBColor(150, 150, 150))
-                            .draw()
-                            .unwrap();
-
-                        chart
-                            .draw_series(LineSeries::new(
+                        draw_plot(|| {
+                            root.fill(&RGBColor(35, 35, 40))?;
+
+                            let mut chart = ChartBuilder::on(&root)
+                                .margin(8)
+                                .x_label_area_size(35)
+                                .y_label_area_size(45)
+                                .build_cartesian_2d(0.0f32..high, 0f32..60f32)?;
+
+                            chart
+                                .configure_mesh()
+                                .x_desc("Frequency (Hz)")
+                                .y_desc("Magnitude (dB)")
+                                .label_style(("sans-serif", 13, &WHITE))
+                                .axis_style(RGBColor(150, 150, 150))
+                                .draw()?;
+
+                            chart.draw_series(LineSeries::new(
                                 left.iter().cloned(),
                                 &RGBColor(255, 80, 80),
-                            ))
-                            .unwrap();
+                            ))?;
 
-                        chart
-                            .draw_series(LineSeries::new(
+                            chart.draw_series(LineSeries::new(
                                 left_cfar.iter().cloned(),
                                 &RGBColor(148, 255, 139),
-                            ))
-                            .unwrap();
+                            ))?;
 
-                        root.present().unwrap();
+                            root.present()?;
+                            Ok(())
+                        });
                     });
 
                 // Bottom panel for Right microphone
@@ -111,81 +299,115 @@ impl eframe::App for Application {
                             .exact_width(ui.available_width() * 0.5)
                             .show_inside(ui, |ui| {
                                 let root = EguiBackend::new(ui).into_drawing_area();
-                                root.fill(&RGBColor(35, 35, 40)).unwrap();
-
-                                let mut chart2 = ChartBuilder::on(&root)
-                                    .margin(8)
-                                    .x_label_area_size(35)
-                                    .y_label_area_size(45)
-                                    .build_cartesian_2d(0.0f32..*high, 0f32..60f32)
-                                    .unwrap();
-
-                                chart2
-                                    .configure_mesh()
-                                    .x_desc("Frequency (Hz)")
-                                    .y_desc("Magnitude (dB)")
-                                    .label_style(("sans-serif", 13, &WHITE))
-                                    .axis_style(&RGBColor(150, 150, 150))
-                                    .draw()
-                                    .unwrap();
-
-                                chart2
-                                    .draw_series(LineSeries::new(
+                                draw_plot(|| {
+                                    root.fill(&RGBColor(35, 35, 40))?;
+
+                                    let mut chart2 = ChartBuilder::on(&root)
+                                        .margin(8)
+                                        .x_label_area_size(35)
+                                        .y_label_area_size(45)
+                                        .build_cartesian_2d(0.0f32..high, 0f32..60f32)?;
+
+                                    chart2
+                                        .configure_mesh()
+                                        .x_desc("Frequency (Hz)")
+                                        .y_desc("Magnitude (dB)")
+                                        .label_style(("sans-serif", 13, &WHITE))
+                                        .axis_style(RGBColor(150, 150, 150))
+                                        .draw()?;
+
+                                    chart2.draw_series(LineSeries::new(
                                         right.iter().cloned(),
                                         &RGBColor(80, 150, 255),
-                                    ))
-                                    .unwrap();
+                                    ))?;
 
-                                chart2
-                                    .draw_series(LineSeries::new(
+                                    chart2.draw_series(LineSeries::new(
                                         right_cfar.iter().cloned(),
                                         &RGBColor(148, 255, 139),
-                                    ))
-                                    .unwrap();
+                                    ))?;
 
-                                root.present().unwrap();
+                                    root.present()?;
+                                    Ok(())
+                                });
                             });
 
                         egui::SidePanel::left("angle_panel_mic")
                             .exact_width(ui.available_width())
                             .show_inside(ui, |ui| {
                                 let root = EguiBackend::new(ui).into_drawing_area();
-                                root.fill(&RGBColor(35, 35, 40)).unwrap();
-
-                                let to_plot: Vec<(f32, f32)> = phases
-                                    .iter()
-                                    .map(|b| (b * 343.0 / 0.055).asin())
-                                    .enumerate()
-                                    .map(|(a, b)| (a as f32, b))
-                                    .collect();
-
-                                let mut chart2 = ChartBuilder::on(&root)
-                                    .margin(8)
-                                    .x_label_area_size(35)
-                                    .y_label_area_size(45)
-                                    .build_cartesian_2d(
-                                        0.0f32..to_plot.len() as f32,
-                                        -1.5f32..1.5f32,
-                                    )
-                                    .unwrap();
-
-                                chart2
-                                    .configure_mesh()
-                                    .x_desc("time")
-                                    .y_desc("angle")
-                                    .label_style(("sans-serif", 13, &WHITE))
-                                    .axis_style(&RGBColor(150, 150, 150))
-                                    .draw()
-                                    .unwrap();
-
-                                chart2
-                                    .draw_series(LineSeries::new(
+                                draw_plot(|| {
+                                    root.fill(&RGBColor(35, 35, 40))?;
+
+                                    let to_plot: Vec<(f32, f32)> = phases
+                                        .iter()
+                                        .map(|b| self.delay_to_angle(*b))
+                                        .enumerate()
+                                        .map(|(a, b)| (a as f32, b))
+                                        .collect();
+
+                                    let history_len = tracks
+                                        .iter()
+                                        .map(|(_, x)| x.len())
+                                        .chain(std::iter::once(to_plot.len()))
+                                        .max()
+                                        .unwrap_or(0);
+
+                                    let mut chart2 = ChartBuilder::on(&root)
+                                        .margin(8)
+                                        .x_label_area_size(35)
+                                        .y_label_area_size(45)
+                                        .build_cartesian_2d(
+                                            0.0f32..history_len as f32,
+                                            -1.5f32..1.5f32,
+                                        )?;
+
+                                    chart2
+                                        .configure_mesh()
+                                        .x_desc("time")
+                                        .y_desc("angle")
+                                        .label_style(("sans-serif", 13, &WHITE))
+                                        .axis_style(RGBColor(150, 150, 150))
+                                        .draw()?;
+
+                                    chart2.draw_series(LineSeries::new(
                                         to_plot.iter().cloned(),
                                         &RGBColor(80, 150, 255),
-                                    ))
-                                    .unwrap();
-
-                                root.present().unwrap();
+                                    ))?;
+
+                                    // quiet stretches of a talker are NaN, dots leave them as gaps
+                                    for (id, angles) in &tracks {
+                                        let color = track_color(*id);
+
+                                        chart2.draw_series(
+                                            angles
+                                                .iter()
+                                                .enumerate()
+                                                .filter(|(_, angle)| angle.is_finite())
+                                                .map(|(i, angle)| {
+                                                    Circle::new(
+                                                        (i as f32, *angle),
+                                                        2,
+                                                        color.filled(),
+                                                    )
+                                                }),
+                                        )?;
+
+                                        if let Some((i, angle)) = angles
+                                            .iter()
+                                            .enumerate()
+                                            .rfind(|(_, angle)| angle.is_finite())
+                                        {
+                                            chart2.draw_series(std::iter::once(Text::new(
+                                                format!("#{id}"),
+                                                (i as f32, *angle + 0.1),
+                                                ("sans-serif", 13).into_font().color(&color),
+                                            )))?;
+                                        }
+                                    }
+
+                                    root.present()?;
+                                    Ok(())
+                                });
                             });
                     });
 
@@ -197,77 +419,117 @@ impl eframe::App for Application {
                         .exact_width(ui.available_width() * 0.65)
                         .show_inside(ui, |ui| {
                             let root = EguiBackend::new(ui).into_drawing_area();
-                            root.fill(&RGBColor(35, 35, 40)).unwrap();
+                            draw_plot(|| {
+                                root.fill(&RGBColor(35, 35, 40))?;
 
-                            let mut chart = ChartBuilder::on(&root)
-                                .margin(8)
-                                .x_label_area_size(35)
-                                .y_label_area_size(45)
-                                .build_cartesian_2d(
-                                    *low_cross..*high_cross,
-                                    -*max_cross..*max_cross,
-                                )
-                                .unwrap();
+                                let mut chart = ChartBuilder::on(&root)
+                                    .margin(8)
+                                    .x_label_area_size(35)
+                                    .y_label_area_size(45)
+                                    .build_cartesian_2d(
+                                        low_cross..high_cross,
+                                        -max_cross..max_cross,
+                                    )?;
 
-                            chart
-                                .configure_mesh()
-                                .x_desc("Time ")
-                                .y_desc("Cross Correlation")
-                                .label_style(("sans-serif", 13, &WHITE))
-                                .axis_style(&RGBColor(150, 150, 150))
-                                .draw()
-                                .unwrap();
+                                chart
+                                    .configure_mesh()
+                                    .x_desc("Time ")
+                                    .y_desc("Cross Correlation")
+                                    .label_style(("sans-serif", 13, &WHITE))
+                                    .axis_style(RGBColor(150, 150, 150))
+                                    .draw()?;
 
-                            chart
-                                .draw_series(LineSeries::new(
+                                chart.draw_series(LineSeries::new(
                                     cross_correlation.iter().cloned(),
                                     &RGBColor(255, 80, 80).mix(0.7),
-                                ))
-                                .unwrap();
+                                ))?;
 
-                            root.present().unwrap();
+                                root.present()?;
+                                Ok(())
+                            });
                         });
 
                     egui::SidePanel::right("right_panel")
                         .exact_width(ui.available_width())
                         .show_inside(ui, |ui| {
                             let root = EguiBackend::new(ui).into_drawing_area();
-                            root.fill(&RGBColor(35, 35, 40)).unwrap();
+                            draw_plot(|| {
+                                root.fill(&RGBColor(35, 35, 40))?;
 
-                            let mut chart = ChartBuilder::on(&root)
-                                .margin(8)
-                                .x_label_area_size(35)
-                                .y_label_area_size(45)
-                                .build_cartesian_2d(-1.0f32..1.0f32, -1.0f32..1.0f32)
-                                .unwrap();
-
-                            chart
-                                .configure_mesh()
-                                .x_desc("X")
-                                .y_desc("Y")
-                                .label_style(("sans-serif", 13, &WHITE))
-                                .axis_style(&RGBColor(150, 150, 150))
-                                .draw()
-                                .unwrap();
-
-                            let time_delay = phases.get(phases.len() - 1).unwrap();
-                            let angle = (time_delay * 343.0 / 0.055).asin();
-
-                            // println!("angle: {}", angle * 180.0 / 3.1415);
-
-                            let mut vec: Vec<(f32, f32)> = Vec::new();
-                            vec.push((0.0, 0.0));
-                            vec.push((angle.sin(), -angle.cos()));
+                                let mut chart = ChartBuilder::on(&root)
+                                    .margin(8)
+                                    .x_label_area_size(35)
+                                    .y_label_area_size(45)
+                                    .build_cartesian_2d(-1.0f32..1.0f32, -1.0f32..1.0f32)?;
 
-                            chart
-                                .draw_series(LineSeries::new(
-                                    //phase_shift,
-                                    vec.iter().cloned(),
-                                    &RGBColor(80, 150, 255).mix(0.7),
-                                ))
-                                .unwrap();
-
-                            root.present().unwrap();
+                                chart
+                                    .configure_mesh()
+                                    .x_desc("X")
+                                    .y_desc("Y")
+                                    .label_style(("sans-serif", 13, &WHITE))
+                                    .axis_style(RGBColor(150, 150, 150))
+                                    .draw()?;
+
+                                if let Some(time_delay) = phases.back() {
+                                    let angle = self.delay_to_angle(*time_delay);
+
+                                    // println!("angle: {}", angle * 180.0 / 3.1415);
+
+                                    let vec: Vec<(f32, f32)> =
+                                        vec![(0.0, 0.0), (angle.sin(), -angle.cos())];
+
+                                    chart.draw_series(LineSeries::new(
+                                        //phase_shift,
+                                        vec.iter().cloned(),
+                                        &RGBColor(80, 150, 255).mix(0.7),
+                                    ))?;
+                                }
+
+                                for (id, angles) in &tracks {
+                                    if let Some(angle) = angles.back().filter(|x| x.is_finite()) {
+                                        let color = track_color(*id);
+                                        let tip = (0.8 * angle.sin(), -0.8 * angle.cos());
+
+                                        chart.draw_series(LineSeries::new(
+                                            vec![(0.0, 0.0), tip],
+                                            &color,
+                                        ))?;
+
+                                        chart.draw_series(std::iter::once(Text::new(
+                                            format!("#{id}"),
+                                            tip,
+                                            ("sans-serif", 13).into_font().color(&color),
+                                        )))?;
+                                    }
+                                }
+
+                                if let Some(map) = &power_map {
+                                    // array y points down the screen, like the pair angle above
+                                    let (polar, best) = srp_polar(map);
+
+                                    chart.draw_series(LineSeries::new(
+                                        polar.iter().cloned(),
+                                        &RGBColor(148, 255, 139).mix(0.7),
+                                    ))?;
+
+                                    chart.draw_series(LineSeries::new(
+                                        vec![(0.0, 0.0), best],
+                                        &RGBColor(255, 80, 80),
+                                    ))?;
+                                }
+
+                                if let Some(spectrum) = &pseudo_spectrum {
+                                    for azimuth in &spectrum.peaks {
+                                        chart.draw_series(LineSeries::new(
+                                            vec![(0.0, 0.0), (azimuth.cos(), -azimuth.sin())],
+                                            &RGBColor(255, 200, 80),
+                                        ))?;
+                                    }
+                                }
+
+                                root.present()?;
+                                Ok(())
+                            });
                         });
                 });
             });
@@ -276,3 +538,46 @@ impl eframe::App for Application {
         ctx.request_repaint();
     }
 }
+
+/// Draws one plot, logging a failure instead of taking the window down with it.
+fn draw_plot(draw: impl FnOnce() -> Result<(), Box<dyn Error>>) {
+    if let Err(e) = draw() {
+        eprintln!("Couldn't draw a plot: {e}");
+    }
+}
+
+fn track_color(id: u32) -> RGBColor {
+    TRACK_COLORS[id as usize % TRACK_COLORS.len()]
+}
+
+/// Normalized power at the elevation of the best direction as a closed polar curve, and the
+/// tip of the best direction, in screen coordinates.
+fn srp_polar(map: &SrpMap) -> (Vec<(f32, f32)>, (f32, f32)) {
+    let row = map
+        .elevations
+        .iter()
+        .position(|x| *x == map.best.elevation)
+        .unwrap_or(0);
+    let power = &map.power[row];
+
+    let low = power.iter().copied().fold(f32::INFINITY, f32::min);
+    let high = power.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = (high - low).max(f32::EPSILON);
+
+    let mut polar: Vec<(f32, f32)> = map
+        .azimuths
+        .iter()
+        .zip(power)
+        .map(|(azimuth, p)| {
+            let r = (p - low) / range;
+            (r * azimuth.cos(), -r * azimuth.sin())
+        })
+        .collect();
+    if let Some(first) = polar.first().copied() {
+        polar.push(first);
+    }
+
+    let best = (map.best.azimuth.cos(), -map.best.azimuth.sin());
+
+    (polar, best)
+}
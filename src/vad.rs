@@ -0,0 +1,279 @@
+use rustfft::num_complex::Complex32;
+use serde::Deserialize;
+
+/// Rule deciding whether a frame contains speech.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case", deny_unknown_fields)]
+pub enum VadMethod {
+    /// In-band energy at least `energy_margin_db` above the tracked noise floor, with a
+    /// spectral flatness below `max_flatness`.
+    ///
+    /// The flatness of any noise frame is about `0.56` whatever its colour, in the voice band
+    /// of a 1024 point frame it rarely drops below `0.45`. Voiced speech stays under `0.3`,
+    /// so the default of `0.4` keeps talkers and drops noise, including the band-limited
+    /// noise of [`SourceKind::SpeechNoise`](crate::generator::SourceKind::SpeechNoise).
+    EnergyFlatness {
+        energy_margin_db: f32,
+        max_flatness: f32,
+    },
+    /// At least `min_bins` in-band bins above their CFAR threshold.
+    CfarCount { min_bins: usize },
+}
+
+/// Marks frames as speech or non-speech so estimates are only reported while someone talks.
+///
+/// A speech decision is held for `hangover` frames so short pauses between words don't
+/// chop the output.
+pub struct VoiceActivityDetector {
+    samples_rate: u32,
+    method: VadMethod,
+    band: (f32, f32),
+    hangover: usize,
+    hang: usize,
+    noise_floor_db: Option<f32>,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(samples_rate: u32, method: VadMethod) -> Self {
+        VoiceActivityDetector {
+            samples_rate,
+            method,
+            band: (300.0, 4000.0),
+            hangover: 5,
+            hang: 0,
+            noise_floor_db: None,
+        }
+    }
+
+    /// Frequency range in Hz the energy, flatness and detection count are computed over.
+    pub fn set_band(&mut self, band: (f32, f32)) {
+        self.band = band;
+    }
+
+    pub fn set_hangover(&mut self, hangover: usize) {
+        self.hangover = hangover;
+    }
+
+    /// Current noise floor estimate of the energy rule, in dB.
+    pub fn noise_floor_db(&self) -> Option<f32> {
+        self.noise_floor_db
+    }
+
+    /// Classifies one frame from its spectrum and optional per-bin CFAR detections.
+    ///
+    /// The CFAR rule treats a missing detection mask as no detections.
+    pub fn is_speech(&mut self, spectrum: &[Complex32], detected: Option<&[bool]>) -> bool {
+        let bins = self.band_bins(spectrum.len());
+
+        let active = match self.method {
+            VadMethod::EnergyFlatness {
+                energy_margin_db,
+                max_flatness,
+            } => {
+                let power: Vec<f32> = bins
+                    .clone()
+                    .map(|i| spectrum[i].norm_sqr().max(f32::MIN_POSITIVE))
+                    .collect();
+
+                if power.is_empty() {
+                    return false;
+                }
+
+                let mean = power.iter().sum::<f32>() / power.len() as f32;
+                let log_mean = power.iter().map(|x| x.ln()).sum::<f32>() / power.len() as f32;
+                let flatness = log_mean.exp() / mean;
+                let energy_db = 10.0 * mean.log10();
+
+                let floor = self.track_noise_floor(energy_db);
+
+                energy_db > floor + energy_margin_db && flatness < max_flatness
+            }
+            VadMethod::CfarCount { min_bins } => {
+                let count = detected.map_or(0, |detected| {
+                    bins.filter(|i| detected.get(*i).copied().unwrap_or(false))
+                        .count()
+                });
+
+                count >= min_bins
+            }
+        };
+
+        if active {
+            self.hang = self.hangover;
+            true
+        } else if self.hang > 0 {
+            self.hang -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn band_bins(&self, fft_len: usize) -> std::ops::Range<usize> {
+        if fft_len == 0 {
+            return 0..0;
+        }
+
+        let resolution = self.samples_rate as f32 / fft_len as f32;
+        let (low, high) = self.band;
+
+        // positive frequencies only, the other half mirrors them for real input
+        let low = ((low / resolution).ceil() as usize).min(fft_len / 2);
+        let high = ((high / resolution).floor() as usize + 1).clamp(low, fft_len / 2);
+
+        low..high
+    }
+
+    fn track_noise_floor(&mut self, energy_db: f32) -> f32 {
+        // follow drops immediately and rises slowly, so speech barely lifts the floor
+        let floor = match self.noise_floor_db {
+            Some(floor) if energy_db > floor => floor + 0.01 * (energy_db - floor),
+            _ => energy_db,
+        };
+
+        self.noise_floor_db = Some(floor);
+        floor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{Framer, Window};
+    use crate::signal::SignalProcessor;
+    use std::f32::consts::PI;
+
+    const RATE: u32 = 48000;
+
+    fn energy_flatness() -> VoiceActivityDetector {
+        VoiceActivityDetector::new(
+            RATE,
+            VadMethod::EnergyFlatness {
+                energy_margin_db: 6.0,
+                max_flatness: 0.4,
+            },
+        )
+    }
+
+    /// Spectra of the Hann windowed 1024 point frames of `samples`.
+    fn spectra(samples: &[f32]) -> Vec<Vec<Complex32>> {
+        let mut processor = SignalProcessor::new(RATE);
+        let mut framer = Framer::new(1024, 512, Window::Hann).unwrap();
+        let samples: Vec<Complex32> = samples.iter().map(|x| Complex32::new(*x, 0.0)).collect();
+
+        framer
+            .push(&samples)
+            .into_iter()
+            .map(|mut frame| processor.fft(&mut frame))
+            .collect()
+    }
+
+    fn noise(len: usize, std: f32, seed: u64) -> Vec<f32> {
+        // sum of twelve uniforms, close enough to gaussian
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                let sum: f32 = (0..12)
+                    .map(|_| {
+                        state = state
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        (state >> 40) as f32 / (1u64 << 24) as f32
+                    })
+                    .sum();
+                std * (sum - 6.0)
+            })
+            .collect()
+    }
+
+    /// Vowel-like source, a 140 Hz fundamental with harmonics falling off at 6 dB an octave.
+    fn voiced(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|n| {
+                let t = n as f32 / RATE as f32;
+                (1..30)
+                    .map(|k| (2.0 * PI * 140.0 * k as f32 * t).sin() / k as f32)
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn voiced_source_is_speech() {
+        let mut vad = energy_flatness();
+        vad.set_hangover(0);
+
+        let quiet = noise(RATE as usize, 0.01, 1);
+        for spectrum in spectra(&quiet) {
+            assert!(!vad.is_speech(&spectrum, None));
+        }
+
+        let talker: Vec<f32> = voiced(RATE as usize)
+            .iter()
+            .zip(noise(RATE as usize, 0.01, 2))
+            .map(|(x, n)| x + n)
+            .collect();
+        let frames = spectra(&talker);
+        // the floor creeps up by a hundredth of the excess every frame
+        assert!(frames.iter().all(|spectrum| vad.is_speech(spectrum, None)));
+    }
+
+    #[test]
+    fn noise_alone_is_not_speech() {
+        let mut vad = energy_flatness();
+        vad.set_hangover(0);
+
+        // a quiet room, then noise 30 dB louder
+        let mut samples = noise(RATE as usize, 0.01, 3);
+        samples.extend(noise(RATE as usize, 0.3, 4));
+
+        let speech = spectra(&samples)
+            .iter()
+            .filter(|spectrum| vad.is_speech(spectrum, None))
+            .count();
+        assert_eq!(speech, 0);
+    }
+
+    #[test]
+    fn hangover_holds_for_exactly_hangover_frames() {
+        let spectrum = vec![Complex32::new(1.0, 0.0); 1024];
+        let active = vec![true; 1024];
+        let silent = vec![false; 1024];
+
+        for hangover in [0, 1, 5] {
+            let mut vad = VoiceActivityDetector::new(RATE, VadMethod::CfarCount { min_bins: 5 });
+            vad.set_hangover(hangover);
+
+            assert!(vad.is_speech(&spectrum, Some(&active)));
+            for frame in 0..hangover {
+                assert!(
+                    vad.is_speech(&spectrum, Some(&silent)),
+                    "{hangover} {frame}"
+                );
+            }
+            assert!(!vad.is_speech(&spectrum, Some(&silent)), "{hangover}");
+
+            // speech during the hangover starts it over
+            assert!(vad.is_speech(&spectrum, Some(&active)));
+            if hangover > 0 {
+                assert!(vad.is_speech(&spectrum, Some(&silent)));
+            }
+        }
+    }
+
+    #[test]
+    fn cfar_count_only_counts_in_band_bins() {
+        let spectrum = vec![Complex32::new(1.0, 0.0); 1024];
+        let mut vad = VoiceActivityDetector::new(RATE, VadMethod::CfarCount { min_bins: 5 });
+        vad.set_hangover(0);
+
+        // bins 0 to 5 are below 300 Hz at 46.9 Hz a bin
+        let mut detected = vec![false; 1024];
+        detected[..6].fill(true);
+        assert!(!vad.is_speech(&spectrum, Some(&detected)));
+
+        detected[7..12].fill(true);
+        assert!(vad.is_speech(&spectrum, Some(&detected)));
+        assert!(!vad.is_speech(&spectrum, None));
+    }
+}